@@ -0,0 +1,102 @@
+//! Times [`upcoming_departures`] over the feed in `fixture/`, five lines into Gare Centrale
+//! every 20 minutes, with a delay on every other trip, along with parsing that feed and
+//! reading its index back. Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use chrono::{DateTime, TimeDelta};
+use chrono_tz::Canada::Eastern;
+use clap::Parser;
+use gtfs_realtime::trip_update::{StopTimeEvent, StopTimeUpdate};
+use gtfs_realtime::{FeedEntity, FeedMessage, TripDescriptor, TripUpdate};
+use gtfs_structures::Gtfs;
+use train_display::{Board, Cli, index, upcoming_departures};
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/fixture");
+const ITERATIONS: u32 = 200;
+
+/// A delay of a few minutes from the first stop of every other trip of `gtfs`.
+fn delays(gtfs: &Gtfs) -> FeedMessage {
+    let mut trip_ids: Vec<&String> = gtfs.trips.keys().collect();
+    trip_ids.sort();
+    let entity = trip_ids
+        .into_iter()
+        .step_by(2)
+        .enumerate()
+        .map(|(i, trip_id)| FeedEntity {
+            id: format!("e-{trip_id}"),
+            trip_update: Some(TripUpdate {
+                trip: TripDescriptor {
+                    trip_id: Some(trip_id.clone()),
+                    ..Default::default()
+                },
+                stop_time_update: vec![StopTimeUpdate {
+                    stop_sequence: Some(1),
+                    departure: Some(StopTimeEvent {
+                        delay: Some(60 * (i % 7) as i32),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .collect();
+    FeedMessage {
+        entity,
+        ..Default::default()
+    }
+}
+
+fn main() {
+    let started = Instant::now();
+    let gtfs = Gtfs::new(FIXTURE).expect("Cannot read the fixture feed");
+    println!("parse fixture: {:?}", started.elapsed());
+
+    // What a run with --static-max-age reads instead once the zip has been parsed.
+    let index_path = std::env::temp_dir().join("train_display-bench.idx");
+    index::write(&index_path, 0, &gtfs).expect("Cannot write the index");
+    let started = Instant::now();
+    let indexed = index::read(&index_path, 0).expect("Cannot read the index back");
+    println!(
+        "read index: {:?}, for {} trips",
+        started.elapsed(),
+        indexed.trips.len()
+    );
+    std::fs::remove_file(&index_path).expect("Cannot remove the index");
+
+    let realtime = delays(&gtfs);
+    let stop_ids: Vec<String> = gtfs
+        .stops
+        .values()
+        .filter(|stop| stop.name.as_deref() == Some("Gare Centrale"))
+        .map(|stop| stop.id.clone())
+        .collect();
+    let args = Cli::parse_from(["train_display", "Gare Centrale"]);
+    let now = DateTime::parse_from_rfc3339("2026-06-01T07:00:00-04:00")
+        .expect("Valid instant")
+        .with_timezone(&Eastern);
+
+    let run = || {
+        upcoming_departures(
+            black_box(&gtfs),
+            black_box(&realtime),
+            &stop_ids,
+            now,
+            (TimeDelta::zero(), None),
+            Board::Departures,
+            &args,
+        )
+    };
+    let count = run().len();
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(run());
+    }
+    println!(
+        "upcoming_departures: {:?} per board of {count} departures",
+        started.elapsed() / ITERATIONS
+    );
+}
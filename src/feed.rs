@@ -0,0 +1,765 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, anyhow, bail};
+use gtfs_realtime::FeedMessage;
+use gtfs_realtime::feed_header::Incrementality;
+use gtfs_structures::{Gtfs, GtfsReader, RawGtfs, StopTime};
+use reqwest::Client;
+
+use crate::index;
+use crate::stream::Stream;
+
+/// Built-in feed locations of an agency, selected with `--agency`.
+#[derive(Debug)]
+pub struct Agency {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub static_url: &'static str,
+    /// `<token>` stands for the API token, when the agency requires one
+    pub realtime_url: &'static str,
+}
+
+impl Agency {
+    pub fn requires_token(&self) -> bool {
+        self.realtime_url.contains("<token>")
+    }
+}
+
+pub const AGENCIES: &[Agency] = &[Agency {
+    name: "exo",
+    description: "exo commuter trains, Montréal",
+    static_url: "https://exo.quebec/xdata/trains/google_transit.zip",
+    realtime_url: "https://exo.chrono-saeiv.com/api/opendata/v1/trains/tripupdate?token=<token>",
+}];
+
+/// Where a feed is read from.
+#[derive(Clone, Debug)]
+pub enum FeedSource {
+    /// Downloaded on every run
+    Url(String),
+    /// Read from a local file (or, for the static feed, an unzipped directory)
+    Path(PathBuf),
+    /// Downloaded to `path`, which is reused while it is younger than `max_age`
+    Cache {
+        url: String,
+        path: PathBuf,
+        max_age: Duration,
+    },
+    /// Pushed by the server as server-sent events, for realtime feeds only
+    Stream { url: String, stream: Arc<Stream> },
+}
+
+impl FeedSource {
+    /// What fetching this source would do, without doing it, for `--dry-run`.
+    pub fn describe(&self) -> String {
+        match self {
+            FeedSource::Url(url) => format!("download {url}"),
+            FeedSource::Path(path) => format!("read {}", path.display()),
+            FeedSource::Cache { url, path, max_age } => match cache_age(path) {
+                Some(age) if age < *max_age => format!(
+                    "reuse {} ({} min old, refreshed after {} min)",
+                    path.display(),
+                    age.as_secs() / 60,
+                    max_age.as_secs() / 60
+                ),
+                Some(_) => format!("download {url} to replace the stale {}", path.display()),
+                None => format!("download {url} to {}", path.display()),
+            },
+            FeedSource::Stream { url, .. } => format!("listen to the events of {url}"),
+        }
+    }
+}
+
+/// The user's cache directory, following the XDG convention.
+pub fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|cache| cache.join("train_display"))
+}
+
+async fn download(client: &Client, url: &str) -> anyhow::Result<Vec<u8>> {
+    let response = client.get(url).send().await?.error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Reads the body of `response`, failing as soon as it is larger than `max_size` bytes
+/// rather than filling up memory.
+async fn read_limited(mut response: reqwest::Response, max_size: usize) -> anyhow::Result<Vec<u8>> {
+    let too_large = || {
+        anyhow!(
+            "The realtime feed is larger than the limit of {} MB",
+            max_size / (1024 * 1024)
+        )
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_size as u64)
+    {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Downloads a realtime feed of at most `max_size` bytes, explaining the response when it is
+/// obviously not protobuf, e.g. an HTML error page or a JSON message about a wrong token.
+async fn download_realtime(client: &Client, url: &str, max_size: usize) -> anyhow::Result<Vec<u8>> {
+    let response = client.get(url).send().await?;
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let bytes = read_limited(response, max_size).await?;
+    let textual = content_type.starts_with("text/") || content_type.contains("json");
+    if !status.is_success() || textual {
+        bail!(
+            "Realtime endpoint answered {status} with {} instead of a protobuf feed \
+             (check the URL and token): {}",
+            if content_type.is_empty() {
+                "no content type"
+            } else {
+                &content_type
+            },
+            body_excerpt(&bytes)
+        );
+    }
+    Ok(bytes)
+}
+
+/// The start of a response body, for error messages.
+fn body_excerpt(bytes: &[u8]) -> String {
+    const MAX_CHARS: usize = 200;
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() > MAX_CHARS {
+        format!("{}...", text.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        text
+    }
+}
+
+/// How long ago the cached copy at `path` was written, if there is one.
+fn cache_age(path: &Path) -> Option<Duration> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+}
+
+/// Refreshes the cached copy of `url` at `path` if it is missing or older than `max_age`.
+async fn refresh_cache(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    max_age: Duration,
+) -> anyhow::Result<()> {
+    let fresh = cache_age(path).is_some_and(|age| age < max_age);
+    if fresh {
+        return Ok(());
+    }
+    let bytes = download(client, url).await?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, bytes).with_context(|| format!("Could not write {}", path.display()))
+}
+
+pub async fn fetch_static(client: &Client, source: &FeedSource) -> anyhow::Result<Gtfs> {
+    let mut gtfs = match source {
+        // Downloaded with `client` rather than by gtfs-structures, so that its proxy applies.
+        FeedSource::Url(url) => GtfsReader::default()
+            .raw()
+            .read_from_reader(std::io::Cursor::new(download(client, url).await?))
+            .and_then(build_gtfs)?,
+        FeedSource::Path(path) => read_static_path(path)?,
+        FeedSource::Cache { url, path, max_age } => {
+            refresh_cache(client, url, path, *max_age).await?;
+            let index_path = path.with_extension("idx");
+            match index::read(&index_path, cache_key(path)?) {
+                Some(gtfs) => gtfs,
+                None => {
+                    let gtfs = read_cached_static(client, url, path, *max_age).await?;
+                    // Keyed again, as the zip is downloaded anew when it was unreadable.
+                    if let Err(err) = index::write(&index_path, cache_key(path)?, &gtfs) {
+                        eprintln!(
+                            "WARNING: Could not write {} ({err}), the static feed will be \
+                             parsed again on the next run",
+                            index_path.display()
+                        );
+                    }
+                    gtfs
+                }
+            }
+        }
+        FeedSource::Stream { .. } => bail!("The static feed cannot be streamed"),
+    };
+    let repaired = repair_stop_order(&mut gtfs);
+    if repaired > 0 {
+        eprintln!(
+            "WARNING: {repaired} trips repeat stop_sequence values; ordered their stops by \
+             distance travelled or time instead"
+        );
+    }
+    // Without either file no trip ever runs, which would look like a quiet station.
+    if gtfs.calendar.is_empty() && gtfs.calendar_dates.is_empty() {
+        bail!(
+            "Static feed contains no service calendar: calendar.txt and calendar_dates.txt are \
+             both empty or missing"
+        );
+    }
+    Ok(gtfs)
+}
+
+fn read_static_path(path: &Path) -> Result<Gtfs, gtfs_structures::Error> {
+    GtfsReader::default()
+        .raw()
+        .read_from_path(path)
+        .and_then(build_gtfs)
+}
+
+/// Parses the cached copy of `url` at `path`, downloading it again when it is unreadable.
+async fn read_cached_static(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    max_age: Duration,
+) -> anyhow::Result<Gtfs> {
+    Ok(match read_static_path(path) {
+        Err(err) if is_truncated(&err) => {
+            // Most likely an interrupted download; fetch it again rather than
+            // failing on every run until the cache expires.
+            eprintln!(
+                "WARNING: Cached static feed {} is unreadable ({err}), downloading it again",
+                path.display()
+            );
+            std::fs::remove_file(path)
+                .with_context(|| format!("Could not remove {}", path.display()))?;
+            refresh_cache(client, url, path, max_age).await?;
+            read_static_path(path)
+                .context("Static feed is still unreadable after downloading it again")?
+        }
+        result => result?,
+    })
+}
+
+/// The key of the index of the cached static feed at `path`, from its path, size and
+/// modification time, which change whenever it is downloaded again, without reading it.
+fn cache_key(path: &Path) -> anyhow::Result<u64> {
+    let metadata =
+        std::fs::metadata(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut identity = path.as_os_str().as_encoded_bytes().to_vec();
+    identity.extend_from_slice(&metadata.len().to_le_bytes());
+    identity.extend_from_slice(&modified.as_nanos().to_le_bytes());
+    Ok(index::feed_key(&identity))
+}
+
+/// Links the files of a static feed together, after working around the inconsistencies of
+/// malformed feeds with a warning.
+fn build_gtfs(mut raw: RawGtfs) -> Result<Gtfs, gtfs_structures::Error> {
+    drop_dangling_stops(&mut raw);
+    drop_duplicate_trips(&mut raw);
+    Gtfs::try_from(raw)
+}
+
+/// Removes the references to stops missing from stops.txt, which would otherwise make the
+/// whole feed unreadable.
+fn drop_dangling_stops(raw: &mut RawGtfs) {
+    if let Ok(stops) = &raw.stops {
+        let known = stops
+            .iter()
+            .map(|stop| stop.id.clone())
+            .collect::<HashSet<_>>();
+        let mut dangling = BTreeSet::new();
+        let mut keep = |stop_id: &String| {
+            let found = known.contains(stop_id);
+            if !found {
+                dangling.insert(stop_id.clone());
+            }
+            found
+        };
+        if let Ok(stop_times) = &mut raw.stop_times {
+            stop_times.retain(|stop_time| keep(&stop_time.stop_id));
+        }
+        if let Some(Ok(transfers)) = &mut raw.transfers {
+            transfers.retain(|transfer| {
+                // Check both ends, to report every missing stop.
+                let from = keep(&transfer.from_stop_id);
+                let to = keep(&transfer.to_stop_id);
+                from && to
+            });
+        }
+        if let Some(Ok(pathways)) = &mut raw.pathways {
+            pathways.retain(|pathway| {
+                // Check both ends, to report every missing stop.
+                let from = keep(&pathway.from_stop_id);
+                let to = keep(&pathway.to_stop_id);
+                from && to
+            });
+        }
+        if !dangling.is_empty() {
+            eprintln!(
+                "WARNING: The static feed refers to stops missing from stops.txt, ignoring them: {}",
+                dangling.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+}
+
+/// Keeps only the first of the trips sharing a trip_id, as merged feeds sometimes have, along
+/// with its stop times: the first run of consecutive stop times of that trip_id, up to a
+/// stop_sequence it already has. Trips are looked up by id everywhere, realtime updates
+/// included, so they could not be told apart anyway; without this, the trip kept would depend
+/// on the file order and their stop times would be mixed.
+fn drop_duplicate_trips(raw: &mut RawGtfs) {
+    let Ok(trips) = &mut raw.trips else {
+        return;
+    };
+    let mut seen = HashSet::new();
+    let mut duplicated = BTreeSet::new();
+    trips.retain(|trip| {
+        let first = seen.insert(trip.id.clone());
+        if !first {
+            duplicated.insert(trip.id.clone());
+        }
+        first
+    });
+    if duplicated.is_empty() {
+        return;
+    }
+    if let Ok(stop_times) = &mut raw.stop_times {
+        let mut previous: Option<String> = None;
+        // Repeated trips whose first trip's stop times have all been seen.
+        let mut complete = HashSet::new();
+        let mut seen = HashSet::new();
+        stop_times.retain(|stop_time| {
+            let trip_id = &stop_time.trip_id;
+            if previous.as_ref() != Some(trip_id)
+                && let Some(previous) = previous.replace(trip_id.clone())
+                && duplicated.contains(&previous)
+            {
+                complete.insert(previous);
+            }
+            if !duplicated.contains(trip_id) {
+                return true;
+            }
+            if complete.contains(trip_id) {
+                return false;
+            }
+            if !seen.insert((trip_id.clone(), stop_time.stop_sequence)) {
+                complete.insert(trip_id.clone());
+                return false;
+            }
+            true
+        });
+    }
+    eprintln!(
+        "WARNING: The static feed repeats trip ids, keeping the first trip of each: {}",
+        duplicated.into_iter().collect::<Vec<_>>().join(", ")
+    );
+}
+
+/// Reorders the stops of trips whose `stop_sequence` values repeat, as some malformed feeds
+/// do, by `shape_dist_traveled` when every stop has one and by time otherwise. Returns the
+/// number of trips reordered.
+fn repair_stop_order(gtfs: &mut Gtfs) -> usize {
+    let mut repaired = 0;
+    for trip in gtfs.trips.values_mut() {
+        let stop_times = &mut trip.stop_times;
+        let duplicated = stop_times
+            .windows(2)
+            .any(|pair| pair[0].stop_sequence == pair[1].stop_sequence);
+        if !duplicated {
+            continue;
+        }
+        if stop_times
+            .iter()
+            .all(|stop_time| stop_time.shape_dist_traveled.is_some())
+        {
+            stop_times.sort_by(|a, b| {
+                let distance = |stop_time: &StopTime| stop_time.shape_dist_traveled.unwrap_or(0.0);
+                distance(a).total_cmp(&distance(b))
+            });
+        } else if stop_times.iter().all(|stop_time| {
+            stop_time
+                .arrival_time
+                .or(stop_time.departure_time)
+                .is_some()
+        }) {
+            stop_times.sort_by_key(|stop_time| stop_time.arrival_time.or(stop_time.departure_time));
+        } else {
+            continue;
+        }
+        repaired += 1;
+    }
+    repaired
+}
+
+/// Whether a static feed failed to parse in a way a partial download would cause.
+fn is_truncated(err: &gtfs_structures::Error) -> bool {
+    matches!(
+        err,
+        gtfs_structures::Error::Zip(_)
+            | gtfs_structures::Error::CSVError { .. }
+            | gtfs_structures::Error::MissingFile(_)
+            | gtfs_structures::Error::NamedFileIO { .. }
+    )
+}
+
+/// Fetches and decodes a realtime feed, refusing downloads larger than `max_size` bytes.
+pub async fn fetch_realtime(
+    client: &Client,
+    source: &FeedSource,
+    max_size: usize,
+) -> anyhow::Result<FeedMessage> {
+    let bytes = match source {
+        FeedSource::Url(url) => download_realtime(client, url, max_size).await?,
+        FeedSource::Path(path) => {
+            std::fs::read(path).with_context(|| format!("Could not read {}", path.display()))?
+        }
+        FeedSource::Cache { url, path, max_age } => {
+            refresh_cache(client, url, path, *max_age).await?;
+            std::fs::read(path)?
+        }
+        FeedSource::Stream { url, stream } => return stream.latest(client, url, max_size).await,
+    };
+    prost::Message::decode(bytes.as_ref()).with_context(|| match std::str::from_utf8(&bytes) {
+        // Valid UTF-8 throughout is a strong hint of an error message rather than corrupt data.
+        Ok(_text) => format!(
+            "Could not decode the realtime feed, which looks like text: {}",
+            body_excerpt(&bytes)
+        ),
+        Err(_binary) => "Could not decode the realtime feed".to_owned(),
+    })
+}
+
+/// Most retries of [`fetch_realtime_retrying`] after the first failure.
+const REALTIME_RETRIES: u32 = 2;
+
+/// Wait before the first retry of [`fetch_realtime_retrying`], doubled for each further one.
+const REALTIME_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Like [`fetch_realtime_merged`], but retries a failed fetch a couple of times with
+/// exponential backoff, riding out the transient errors feeds return now and then. No retry
+/// starts past `budget` from the first attempt, so that a watch refresh keeps to its interval.
+pub async fn fetch_realtime_retrying(
+    client: &Client,
+    sources: &[FeedSource],
+    max_size: usize,
+    budget: Duration,
+    mut snapshots: Option<&mut Snapshots>,
+) -> anyhow::Result<FeedMessage> {
+    let start = tokio::time::Instant::now();
+    let mut backoff = REALTIME_BACKOFF;
+    for _retry in 0..REALTIME_RETRIES {
+        match fetch_realtime_merged(client, sources, max_size, snapshots.as_deref_mut()).await {
+            Ok(message) => return Ok(message),
+            Err(err) if start.elapsed() + backoff <= budget => {
+                eprintln!(
+                    "WARNING: Could not load the realtime feed ({err:#}), retrying in {} ms",
+                    backoff.as_millis()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    fetch_realtime_merged(client, sources, max_size, snapshots).await
+}
+
+/// Waits until one of the streamed `sources` receives a message, forever if none is streamed.
+pub async fn next_push(sources: &[FeedSource]) {
+    let updates = sources.iter().filter_map(|source| match source {
+        FeedSource::Stream { stream, .. } => Some(Box::pin(stream.next_update())),
+        _ => None,
+    });
+    let updates = updates.collect::<Vec<_>>();
+    if updates.is_empty() {
+        return std::future::pending().await;
+    }
+    futures::future::select_all(updates).await;
+}
+
+/// Applies `message` to `previous`, the feed last received from the same source: the
+/// entities of a DIFFERENTIAL message replace, or delete, those with the same id, and any
+/// other message replaces the whole feed.
+pub fn apply_incremental(previous: Option<FeedMessage>, message: FeedMessage) -> FeedMessage {
+    match previous {
+        Some(mut current) if message.header.incrementality() == Incrementality::Differential => {
+            for entity in message.entity {
+                current.entity.retain(|existing| existing.id != entity.id);
+                if !entity.is_deleted() {
+                    current.entity.push(entity);
+                }
+            }
+            current.header = message.header;
+            current
+        }
+        _ => message,
+    }
+}
+
+/// The realtime feed last received from each polled source, in watch mode, for the next
+/// DIFFERENTIAL messages to apply to. Streamed sources keep their own.
+#[derive(Debug, Default)]
+pub struct Snapshots(Vec<Option<FeedMessage>>);
+
+/// Fetches several realtime feeds concurrently and merges their entities into one message,
+/// for agencies that publish trip updates, vehicle positions and alerts separately.
+///
+/// A DIFFERENTIAL message only holds what changed since the previous one, so it is applied to
+/// the feed in `snapshots` when watching, and taken on its own otherwise.
+pub async fn fetch_realtime_merged(
+    client: &Client,
+    sources: &[FeedSource],
+    max_size: usize,
+    snapshots: Option<&mut Snapshots>,
+) -> anyhow::Result<FeedMessage> {
+    let mut messages = futures::future::try_join_all(
+        sources
+            .iter()
+            .map(|source| fetch_realtime(client, source, max_size)),
+    )
+    .await?;
+    let polled = sources
+        .iter()
+        .zip(&mut messages)
+        .enumerate()
+        .filter(|(_index, (source, _message))| !matches!(source, FeedSource::Stream { .. }));
+    match snapshots {
+        Some(Snapshots(snapshots)) => {
+            snapshots.resize(sources.len(), None);
+            for (index, (_source, message)) in polled {
+                let merged = apply_incremental(snapshots[index].take(), std::mem::take(message));
+                snapshots[index] = Some(merged.clone());
+                *message = merged;
+            }
+        }
+        None => {
+            if polled.into_iter().any(|(_index, (_source, message))| {
+                message.header.incrementality() == Incrementality::Differential
+            }) {
+                eprintln!(
+                    "WARNING: The realtime feed is DIFFERENTIAL, so outside watch mode only its latest changes are known"
+                );
+            }
+        }
+    }
+    let mut messages = messages.into_iter();
+    let mut merged = messages.next().context("No realtime feed configured")?;
+    for message in messages {
+        merged.entity.extend(message.entity);
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use gtfs_structures::{
+        Calendar, RawStopTime, RawTransfer, RawTrip, Route, SourceFormat, Stop, TransferType,
+    };
+
+    use super::*;
+
+    /// A feed with the stops `stop_ids`, and with the trips `trip_ids` running every day of 2026
+    /// on route `R1`, calling as given by `stop_times` in file order.
+    fn raw_feed(stop_ids: &[&str], trip_ids: &[&str], stop_times: Vec<RawStopTime>) -> RawGtfs {
+        let stops = stop_ids
+            .iter()
+            .map(|id| Stop {
+                id: (*id).to_owned(),
+                name: Some(format!("Station {id}")),
+                ..Default::default()
+            })
+            .collect();
+        let trips = trip_ids
+            .iter()
+            .map(|id| RawTrip {
+                id: (*id).to_owned(),
+                service_id: "daily".to_owned(),
+                route_id: "R1".to_owned(),
+                ..Default::default()
+            })
+            .collect();
+        let calendar = Calendar {
+            id: "daily".to_owned(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: true,
+            sunday: true,
+            start_date: chrono::NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            end_date: chrono::NaiveDate::from_ymd_opt(2026, 12, 31).unwrap(),
+        };
+        let route = Route {
+            id: "R1".to_owned(),
+            ..Default::default()
+        };
+        RawGtfs {
+            read_duration: Duration::ZERO,
+            calendar: Some(Ok(vec![calendar])),
+            calendar_dates: None,
+            stops: Ok(stops),
+            routes: Ok(vec![route]),
+            trips: Ok(trips),
+            agencies: Ok(Vec::new()),
+            shapes: None,
+            fare_attributes: None,
+            fare_rules: None,
+            fare_products: None,
+            fare_media: None,
+            rider_categories: None,
+            frequencies: None,
+            transfers: None,
+            pathways: None,
+            feed_info: None,
+            stop_times: Ok(stop_times),
+            files: Vec::new(),
+            source_format: SourceFormat::Directory,
+            sha256: None,
+            translations: None,
+        }
+    }
+
+    /// A call of `trip_id` at `stop_id`, arriving and leaving `seconds` after midnight.
+    fn stop_time(trip_id: &str, stop_id: &str, stop_sequence: u32, seconds: u32) -> RawStopTime {
+        RawStopTime {
+            trip_id: trip_id.to_owned(),
+            stop_id: stop_id.to_owned(),
+            stop_sequence,
+            arrival_time: Some(seconds),
+            departure_time: Some(seconds),
+            ..Default::default()
+        }
+    }
+
+    /// The stop ids of each call of `trip_id` in `gtfs`, in order.
+    fn calls(gtfs: &Gtfs, trip_id: &str) -> Vec<String> {
+        gtfs.trips[trip_id]
+            .stop_times
+            .iter()
+            .map(|stop_time| stop_time.stop.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn repeated_stop_sequences_are_ordered_by_distance_or_time() {
+        let with_distance = |stop_id, seconds, distance| RawStopTime {
+            shape_dist_traveled: Some(distance),
+            ..stop_time("by_distance", stop_id, 0, seconds)
+        };
+        let stop_times = vec![
+            // Times that disagree with the distances, which take precedence.
+            with_distance("C", 3300, 2.0),
+            with_distance("A", 3000, 0.0),
+            with_distance("B", 3600, 1.5),
+            stop_time("by_time", "C", 1, 7800),
+            stop_time("by_time", "A", 1, 7200),
+            stop_time("by_time", "B", 1, 7500),
+            stop_time("ordered", "A", 1, 7200),
+            stop_time("ordered", "B", 2, 7500),
+        ];
+        let raw = raw_feed(
+            &["A", "B", "C"],
+            &["by_distance", "by_time", "ordered"],
+            stop_times,
+        );
+        let mut gtfs = build_gtfs(raw).unwrap();
+        assert_eq!(repair_stop_order(&mut gtfs), 2);
+        assert_eq!(calls(&gtfs, "by_distance"), ["A", "B", "C"]);
+        assert_eq!(calls(&gtfs, "by_time"), ["A", "B", "C"]);
+        assert_eq!(calls(&gtfs, "ordered"), ["A", "B"]);
+    }
+
+    #[test]
+    fn references_to_missing_stops_are_dropped() {
+        let dangling = || {
+            let stop_times = vec![
+                stop_time("t1", "A", 1, 3600),
+                stop_time("t1", "XX", 2, 3900),
+                stop_time("t1", "B", 3, 4200),
+            ];
+            let mut raw = raw_feed(&["A", "B"], &["t1"], stop_times);
+            let transfer = |to_stop_id: &str| RawTransfer {
+                from_stop_id: "A".to_owned(),
+                to_stop_id: to_stop_id.to_owned(),
+                transfer_type: TransferType::Recommended,
+                min_transfer_time: None,
+            };
+            raw.transfers = Some(Ok(vec![transfer("XX"), transfer("B")]));
+            raw
+        };
+        assert!(Gtfs::try_from(dangling()).is_err());
+
+        let gtfs = build_gtfs(dangling()).unwrap();
+        assert_eq!(calls(&gtfs, "t1"), ["A", "B"]);
+        let transfers = gtfs.stops["A"]
+            .transfers
+            .iter()
+            .map(|transfer| transfer.to_stop_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(transfers, ["B"]);
+    }
+
+    #[test]
+    fn repeated_trip_ids_keep_the_first_trip() {
+        let stop_times = vec![
+            stop_time("t1", "A", 1, 3600),
+            stop_time("t1", "B", 2, 4200),
+            stop_time("t2", "A", 1, 5400),
+            stop_time("t2", "B", 2, 6000),
+            // The stop times of the second t1, listed after the first one's.
+            stop_time("t1", "A", 1, 7200),
+            stop_time("t1", "C", 2, 7800),
+            stop_time("t3", "A", 1, 3600),
+            stop_time("t3", "B", 2, 4200),
+            // A second t3 listed right after the first one, then a third numbering its stops
+            // on from the first one's.
+            stop_time("t3", "A", 1, 7200),
+            stop_time("t3", "C", 2, 7800),
+            stop_time("t2", "C", 3, 6600),
+            stop_time("t3", "C", 3, 7800),
+            stop_time("t3", "D", 4, 8400),
+        ];
+        let mut raw = raw_feed(
+            &["A", "B", "C", "D"],
+            &["t1", "t2", "t1", "t3", "t3", "t3"],
+            stop_times,
+        );
+        if let Ok(trips) = &mut raw.trips {
+            trips[2].trip_headsign = Some("Second".to_owned());
+        }
+        let gtfs = build_gtfs(raw).unwrap();
+        assert_eq!(gtfs.trips.len(), 3);
+        let t1 = &gtfs.trips["t1"];
+        assert_eq!(t1.trip_headsign, None);
+        assert_eq!(calls(&gtfs, "t1"), ["A", "B"]);
+        let times = t1
+            .stop_times
+            .iter()
+            .map(|stop_time| stop_time.departure_time)
+            .collect::<Vec<_>>();
+        assert_eq!(times, [Some(3600), Some(4200)]);
+        assert_eq!(calls(&gtfs, "t2"), ["A", "B", "C"]);
+        assert_eq!(calls(&gtfs, "t3"), ["A", "B"]);
+    }
+}
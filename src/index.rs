@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{Datelike, NaiveDate};
+use gtfs_structures::{
+    Agency, Calendar, CalendarDate, Exception, FeedInfo, Gtfs, PickupDropOffType, Route, Stop,
+    StopTime, StopTransfer, TimepointType, TransferType, Trip,
+};
+
+/// Start of every index file, followed by the format version.
+const MAGIC: &[u8; 4] = b"TDIX";
+
+/// Bumped whenever the layout below changes, so that older indexes are parsed again.
+const VERSION: u32 = 1;
+
+/// A key for the static feed identified by `bytes`, stable across runs unlike the standard
+/// hashers: 64-bit FNV-1a.
+pub fn feed_key(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Writes the parts of `gtfs` that the board reads to `path`, for the feed of `key`: stops
+/// with their transfers, routes, trips with their stop times, calendars and the feed info.
+/// Everything else, shapes and fares among others, is left out.
+pub fn write(path: &Path, key: u64, gtfs: &Gtfs) -> std::io::Result<()> {
+    let mut out = Writer(MAGIC.to_vec());
+    out.u32(VERSION);
+    out.u64(key);
+
+    let mut stops: Vec<&Arc<Stop>> = gtfs.stops.values().collect();
+    stops.sort_by(|a, b| a.id.cmp(&b.id));
+    let positions: HashMap<&str, u32> = stops
+        .iter()
+        .enumerate()
+        .map(|(position, stop)| (stop.id.as_str(), position as u32))
+        .collect();
+    out.len(stops.len());
+    for stop in &stops {
+        out.str(&stop.id);
+        out.opt_str(stop.code.as_deref());
+        out.opt_str(stop.name.as_deref());
+        out.opt_str(stop.platform_code.as_deref());
+        out.len(stop.transfers.len());
+        for transfer in &stop.transfers {
+            out.str(&transfer.to_stop_id);
+            out.u8(transfer_type_code(transfer.transfer_type));
+            out.opt_u32(transfer.min_transfer_time);
+        }
+    }
+
+    out.len(gtfs.routes.len());
+    for route in gtfs.routes.values() {
+        out.str(&route.id);
+        out.opt_str(route.short_name.as_deref());
+        out.opt_str(route.long_name.as_deref());
+        out.opt_u32(route.order);
+    }
+
+    out.len(gtfs.trips.len());
+    for trip in gtfs.trips.values() {
+        out.str(&trip.id);
+        out.str(&trip.service_id);
+        out.str(&trip.route_id);
+        out.opt_str(trip.trip_headsign.as_deref());
+        out.opt_str(trip.trip_short_name.as_deref());
+        out.opt_str(trip.block_id.as_deref());
+        out.len(trip.stop_times.len());
+        for stop_time in &trip.stop_times {
+            // Stop times refer to their stop by its position, to be shared again when read.
+            let Some(position) = positions.get(stop_time.stop.id.as_str()) else {
+                return Err(std::io::Error::other(format!(
+                    "Trip {} calls at unknown stop {}",
+                    trip.id, stop_time.stop.id
+                )));
+            };
+            out.u32(*position);
+            out.opt_u32(stop_time.arrival_time);
+            out.opt_u32(stop_time.departure_time);
+            out.i16(pickup_drop_off_code(stop_time.pickup_type));
+            out.i16(pickup_drop_off_code(stop_time.drop_off_type));
+            out.u32(stop_time.stop_sequence);
+            out.opt_f32(stop_time.shape_dist_traveled);
+            out.u8(stop_time.timepoint as u8);
+        }
+    }
+
+    out.len(gtfs.calendar.len());
+    for calendar in gtfs.calendar.values() {
+        out.str(&calendar.id);
+        let days = [
+            calendar.monday,
+            calendar.tuesday,
+            calendar.wednesday,
+            calendar.thursday,
+            calendar.friday,
+            calendar.saturday,
+            calendar.sunday,
+        ];
+        out.u8(days
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (day, runs)| bits | (u8::from(*runs) << day)));
+        out.date(calendar.start_date);
+        out.date(calendar.end_date);
+    }
+
+    let dates: Vec<&CalendarDate> = gtfs.calendar_dates.values().flatten().collect();
+    out.len(dates.len());
+    for date in dates {
+        out.str(&date.service_id);
+        out.date(date.date);
+        out.u8(match date.exception_type {
+            Exception::Added => 1,
+            Exception::Deleted => 2,
+        });
+    }
+
+    out.len(gtfs.feed_info.len());
+    for info in &gtfs.feed_info {
+        out.str(&info.name);
+        out.str(&info.url);
+        out.str(&info.lang);
+        out.opt_date(info.start_date);
+        out.opt_date(info.end_date);
+        out.opt_str(info.version.as_deref());
+    }
+
+    out.len(gtfs.agencies.len());
+    for agency in &gtfs.agencies {
+        out.opt_str(agency.id.as_deref());
+        out.str(&agency.name);
+        out.str(&agency.url);
+        out.str(&agency.timezone);
+    }
+
+    std::fs::write(path, out.0)
+}
+
+/// Reads the index at `path` back into a feed, if it is one written by [`write`] in this
+/// format for the feed of `key`.
+pub fn read(path: &Path, key: u64) -> Option<Gtfs> {
+    let bytes = std::fs::read(path).ok()?;
+    let mut input = Reader(&bytes);
+    if input.take(MAGIC.len())? != MAGIC || input.u32()? != VERSION || input.u64()? != key {
+        return None;
+    }
+
+    let mut stops = Vec::new();
+    for _ in 0..input.u32()? {
+        let mut stop = Stop {
+            id: input.str()?,
+            code: input.opt_str()?,
+            name: input.opt_str()?,
+            platform_code: input.opt_str()?,
+            ..Default::default()
+        };
+        for _ in 0..input.u32()? {
+            stop.transfers.push(StopTransfer {
+                to_stop_id: input.str()?,
+                transfer_type: transfer_type(input.u8()?)?,
+                min_transfer_time: input.opt_u32()?,
+            });
+        }
+        stops.push(Arc::new(stop));
+    }
+
+    let mut routes = HashMap::new();
+    for _ in 0..input.u32()? {
+        let route = Route {
+            id: input.str()?,
+            short_name: input.opt_str()?,
+            long_name: input.opt_str()?,
+            order: input.opt_u32()?,
+            ..Default::default()
+        };
+        routes.insert(route.id.clone(), route);
+    }
+
+    let mut trips = HashMap::new();
+    for _ in 0..input.u32()? {
+        let mut trip = Trip {
+            id: input.str()?,
+            service_id: input.str()?,
+            route_id: input.str()?,
+            trip_headsign: input.opt_str()?,
+            trip_short_name: input.opt_str()?,
+            block_id: input.opt_str()?,
+            ..Default::default()
+        };
+        for _ in 0..input.u32()? {
+            trip.stop_times.push(StopTime {
+                stop: Arc::clone(stops.get(input.u32()? as usize)?),
+                arrival_time: input.opt_u32()?,
+                departure_time: input.opt_u32()?,
+                pickup_type: pickup_drop_off(input.i16()?),
+                drop_off_type: pickup_drop_off(input.i16()?),
+                stop_sequence: input.u32()?,
+                shape_dist_traveled: input.opt_f32()?,
+                timepoint: match input.u8()? {
+                    0 => TimepointType::Approximate,
+                    _ => TimepointType::Exact,
+                },
+                ..Default::default()
+            });
+        }
+        trips.insert(trip.id.clone(), trip);
+    }
+
+    let mut calendar = HashMap::new();
+    for _ in 0..input.u32()? {
+        let id = input.str()?;
+        let days = input.u8()?;
+        let runs = |day: u8| days & (1 << day) != 0;
+        let service = Calendar {
+            id: id.clone(),
+            monday: runs(0),
+            tuesday: runs(1),
+            wednesday: runs(2),
+            thursday: runs(3),
+            friday: runs(4),
+            saturday: runs(5),
+            sunday: runs(6),
+            start_date: input.date()?,
+            end_date: input.date()?,
+        };
+        calendar.insert(id, service);
+    }
+
+    let mut calendar_dates: HashMap<String, Vec<CalendarDate>> = HashMap::new();
+    for _ in 0..input.u32()? {
+        let date = CalendarDate {
+            service_id: input.str()?,
+            date: input.date()?,
+            exception_type: match input.u8()? {
+                1 => Exception::Added,
+                2 => Exception::Deleted,
+                _ => return None,
+            },
+        };
+        calendar_dates
+            .entry(date.service_id.clone())
+            .or_default()
+            .push(date);
+    }
+
+    let mut feed_info = Vec::new();
+    for _ in 0..input.u32()? {
+        feed_info.push(FeedInfo {
+            name: input.str()?,
+            url: input.str()?,
+            lang: input.str()?,
+            start_date: input.opt_date()?,
+            end_date: input.opt_date()?,
+            version: input.opt_str()?,
+            default_lang: None,
+            contact_email: None,
+            contact_url: None,
+        });
+    }
+
+    let mut agencies = Vec::new();
+    for _ in 0..input.u32()? {
+        agencies.push(Agency {
+            id: input.opt_str()?,
+            name: input.str()?,
+            url: input.str()?,
+            timezone: input.str()?,
+            ..Default::default()
+        });
+    }
+
+    if !input.0.is_empty() {
+        return None;
+    }
+    Some(Gtfs {
+        stops: stops
+            .into_iter()
+            .map(|stop| (stop.id.clone(), stop))
+            .collect(),
+        routes,
+        trips,
+        calendar,
+        calendar_dates,
+        feed_info,
+        agencies,
+        ..Default::default()
+    })
+}
+
+fn transfer_type_code(transfer_type: TransferType) -> u8 {
+    match transfer_type {
+        TransferType::Recommended => 0,
+        TransferType::Timed => 1,
+        TransferType::MinTime => 2,
+        TransferType::Impossible => 3,
+        TransferType::StayOnBoard => 4,
+        TransferType::MustAlight => 5,
+    }
+}
+
+fn transfer_type(code: u8) -> Option<TransferType> {
+    Some(match code {
+        0 => TransferType::Recommended,
+        1 => TransferType::Timed,
+        2 => TransferType::MinTime,
+        3 => TransferType::Impossible,
+        4 => TransferType::StayOnBoard,
+        5 => TransferType::MustAlight,
+        _ => return None,
+    })
+}
+
+/// The value of `kind` in stop_times.txt.
+fn pickup_drop_off_code(kind: PickupDropOffType) -> i16 {
+    match kind {
+        PickupDropOffType::Regular => 0,
+        PickupDropOffType::NotAvailable => 1,
+        PickupDropOffType::ArrangeByPhone => 2,
+        PickupDropOffType::CoordinateWithDriver => 3,
+        PickupDropOffType::Unknown(code) => code,
+    }
+}
+
+fn pickup_drop_off(code: i16) -> PickupDropOffType {
+    match code {
+        0 => PickupDropOffType::Regular,
+        1 => PickupDropOffType::NotAvailable,
+        2 => PickupDropOffType::ArrangeByPhone,
+        3 => PickupDropOffType::CoordinateWithDriver,
+        code => PickupDropOffType::Unknown(code),
+    }
+}
+
+/// Little-endian values, with strings and lists prefixed by their length.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn i16(&mut self, value: i16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.len(value.len());
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn date(&mut self, date: NaiveDate) {
+        self.0
+            .extend_from_slice(&date.num_days_from_ce().to_le_bytes());
+    }
+
+    /// A presence byte, followed by the value when there is one.
+    fn opt<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) {
+        self.u8(u8::from(value.is_some()));
+        if let Some(value) = value {
+            write(self, value);
+        }
+    }
+
+    fn opt_str(&mut self, value: Option<&str>) {
+        self.opt(value, Self::str);
+    }
+
+    fn opt_u32(&mut self, value: Option<u32>) {
+        self.opt(value, Self::u32);
+    }
+
+    fn opt_f32(&mut self, value: Option<f32>) {
+        self.opt(value, |out, value| out.u32(value.to_bits()));
+    }
+
+    fn opt_date(&mut self, value: Option<NaiveDate>) {
+        self.opt(value, Self::date);
+    }
+}
+
+/// The rest of an index being read, each method consuming one value written by [`Writer`] or
+/// giving `None` past the end.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.array::<1>()?[0])
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        Some(i16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.array()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn date(&mut self) -> Option<NaiveDate> {
+        NaiveDate::from_num_days_from_ce_opt(i32::from_le_bytes(self.array()?))
+    }
+
+    fn opt<T>(&mut self, read: impl FnOnce(&mut Self) -> Option<T>) -> Option<Option<T>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => read(self).map(Some),
+        }
+    }
+
+    fn opt_str(&mut self) -> Option<Option<String>> {
+        self.opt(Self::str)
+    }
+
+    fn opt_u32(&mut self) -> Option<Option<u32>> {
+        self.opt(Self::u32)
+    }
+
+    fn opt_f32(&mut self) -> Option<Option<f32>> {
+        self.opt(|input| input.u32().map(f32::from_bits))
+    }
+
+    fn opt_date(&mut self) -> Option<Option<NaiveDate>> {
+        self.opt(Self::date)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_reads_back_what_the_board_uses() {
+        let gtfs = Gtfs::new(concat!(env!("CARGO_MANIFEST_DIR"), "/benches/fixture")).unwrap();
+        let path = std::env::temp_dir().join(format!("train_display-{}.idx", std::process::id()));
+        write(&path, 42, &gtfs).unwrap();
+        let other_feed = read(&path, 41);
+        let indexed = read(&path, 42);
+        std::fs::remove_file(&path).unwrap();
+        assert!(other_feed.is_none());
+        let indexed = indexed.unwrap();
+
+        let calls = |gtfs: &Gtfs| {
+            let mut calls = gtfs
+                .trips
+                .values()
+                .flat_map(|trip| {
+                    trip.stop_times.iter().map(|stop_time| {
+                        (
+                            trip.id.clone(),
+                            trip.trip_headsign.clone(),
+                            stop_time.stop.name.clone(),
+                            stop_time.stop_sequence,
+                            stop_time.departure_time,
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            calls.sort();
+            calls
+        };
+        assert_eq!(calls(&indexed), calls(&gtfs));
+        for (id, stop) in &gtfs.stops {
+            assert_eq!(indexed.stops[id].name, stop.name);
+        }
+        for (id, route) in &gtfs.routes {
+            assert_eq!(indexed.routes[id].short_name, route.short_name);
+        }
+        for (id, calendar) in &gtfs.calendar {
+            let copy = &indexed.calendar[id];
+            assert_eq!(
+                (copy.monday, copy.saturday, copy.start_date, copy.end_date),
+                (
+                    calendar.monday,
+                    calendar.saturday,
+                    calendar.start_date,
+                    calendar.end_date
+                )
+            );
+        }
+        assert_eq!(indexed.agencies.len(), gtfs.agencies.len());
+    }
+}
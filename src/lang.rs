@@ -0,0 +1,148 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+/// Language of the tool's own labels in human output. Station names and headsigns come from
+/// the feed and are printed as they are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    Fr,
+}
+
+/// Labels translated to French, by their English text; `{}` stands for the values filled in.
+const FRENCH: &[(&str, &str)] = &[
+    ("cancelled", "annulé"),
+    ("departed", "parti"),
+    ("extra train", "train supplémentaire"),
+    ("first you can catch", "premier à votre portée"),
+    ("pickup only", "montée seulement"),
+    ("drop-off only", "descente seulement"),
+    ("no pickup or drop-off", "ni montée ni descente"),
+    ("now platform {}", "maintenant quai {}"),
+    (", not {}", ", au lieu du {}"),
+    ("{} (changed from {})", "{} (au lieu du {})"),
+    ("{} (changed)", "{} (changé)"),
+    ("Train {} to {}", "Train {} vers {}"),
+    ("to {}: {}", "vers {} : {}"),
+    ("stops at {}", "s'arrête à {}"),
+    ("arrives {}", "arrive à {}"),
+    ("vehicle {}", "véhicule {}"),
+    ("{} confidence", "confiance {}"),
+    ("high", "élevée"),
+    ("medium", "moyenne"),
+    ("low", "faible"),
+    ("continues to {}", "continue vers {}"),
+    ("block {}", "bloc {}"),
+    ("raw delay {} s", "retard brut {} s"),
+    (" from {}", " selon {}"),
+    ("{} trains cancelled ({}–{})", "{} trains annulés ({}–{})"),
+    ("in {} min", "dans {} min"),
+    ("1 departure", "1 départ"),
+    ("{} departures", "{} départs"),
+    (", {} to {}", ", de {} à {}"),
+    (
+        ", {} delayed by {} min on average",
+        ", {} en retard de {} min en moyenne",
+    ),
+    (", {} cancelled", ", {} annulés"),
+    ("non-stop", "sans arrêt"),
+    ("1 stop", "1 arrêt"),
+    ("{} stops", "{} arrêts"),
+    ("No upcoming departures", "Aucun départ à venir"),
+    ("yesterday", "hier"),
+    ("today", "aujourd'hui"),
+    ("tomorrow", "demain"),
+    ("Last train: {} to {}, {}", "Dernier train : {} vers {}, {}"),
+    (
+        "First train: {} to {}, in {}",
+        "Premier train : {} vers {}, dans {}",
+    ),
+    ("(no headsign)", "(sans destination)"),
+    ("left {} ago", "parti il y a {}"),
+    ("in {}", "dans {}"),
+    (
+        ", change ({} min{}), {} → {} {}",
+        ", correspondance ({} min{}), {} → {} {}",
+    ),
+    (", no connection", ", aucune correspondance"),
+    ("timed transfer", "correspondance garantie"),
+    ("stay on board", "restez à bord"),
+    (
+        "at least {} min per the agency",
+        "au moins {} min selon l'exploitant",
+    ),
+    (
+        "Data may be stale (captured {})",
+        "Données possiblement périmées (captées le {})",
+    ),
+    ("-- more --", "-- suite --"),
+    (
+        "No realtime updates at the moment; times are as scheduled",
+        "Aucune donnée en temps réel pour le moment; horaires prévus",
+    ),
+];
+
+impl Lang {
+    /// The language of the user's locale, from `LC_ALL`, `LC_MESSAGES` or `LANG` like the C
+    /// library does, English unless it is French.
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        if locale == "fr" || locale.starts_with("fr_") || locale.starts_with("fr.") {
+            Lang::Fr
+        } else {
+            Lang::En
+        }
+    }
+
+    /// `english` in this language, or as is when it has no translation.
+    pub fn tr(self, english: &'static str) -> &'static str {
+        let table = match self {
+            Lang::En => return english,
+            Lang::Fr => FRENCH,
+        };
+        table
+            .iter()
+            .find(|(key, _)| *key == english)
+            .map_or(english, |(_, translated)| translated)
+    }
+
+    /// Like [`Lang::tr`], with each `{}` replaced by the next of `values`.
+    pub fn fill(self, english: &'static str, values: &[&dyn Display]) -> String {
+        let mut parts = self.tr(english).split("{}");
+        let mut filled = parts.next().unwrap_or_default().to_owned();
+        for (part, value) in parts.zip(values) {
+            filled.push_str(&value.to_string());
+            filled.push_str(part);
+        }
+        filled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translations_keep_every_value() {
+        for (english, french) in FRENCH {
+            assert_eq!(
+                english.matches("{}").count(),
+                french.matches("{}").count(),
+                "{english}"
+            );
+        }
+        assert_eq!(
+            Lang::Fr.fill(
+                "Last train: {} to {}, {}",
+                &[&"23:30", &"Saint-Jérôme", &"dans 5 min"]
+            ),
+            "Dernier train : 23:30 vers Saint-Jérôme, dans 5 min"
+        );
+    }
+}
@@ -0,0 +1,4196 @@
+use std::cell::OnceCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, bail};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, Days, FixedOffset, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Canada::Eastern;
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueEnum};
+use gtfs_realtime::trip_descriptor::ScheduleRelationship;
+use gtfs_realtime::trip_update::StopTimeUpdate;
+use gtfs_realtime::trip_update::stop_time_update::ScheduleRelationship as StopRelationship;
+use gtfs_realtime::{FeedMessage, TripUpdate};
+use gtfs_structures::{
+    Calendar, Exception, Gtfs, PickupDropOffType, StopTime, TimepointType, TransferType, Trip,
+};
+use reqwest::Client;
+use tokio::join;
+
+use crate::clock::{Clock, FixedClock, SystemClock};
+use crate::feed::{Agency, FeedSource};
+use crate::lang::Lang;
+use crate::output::{Note, OutputFormat, RenderContext};
+
+mod clock;
+mod feed;
+pub mod index;
+mod json;
+mod lang;
+mod output;
+mod stream;
+
+const DAY_TRANSITION: NaiveTime = NaiveTime::from_hms_opt(2, 0, 0).unwrap();
+
+/// Largest number of minutes the options given in minutes accept: a day.
+const MAX_MINUTES: i64 = 24 * 60;
+
+#[derive(Parser, Debug)]
+#[command(name = "train_display")]
+#[command(about = "Work in progress", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Station name, or an alias defined in the alias file
+    #[arg(required = true, env = "TRAIN_DISPLAY_STATION")]
+    station: Option<String>,
+    /// Also list the departures of this station; repeat it for more stations
+    #[arg(long, value_name = "STATION")]
+    also: Vec<String>,
+    /// List the departures of each station under its own header, rather than all together
+    #[arg(long, value_enum, conflicts_with_all = ["format", "next", "last", "first"])]
+    group_by: Option<GroupBy>,
+    /// File mapping short aliases to station names, one `alias[, alias...] = "Station"` per line
+    #[arg(long)]
+    aliases: Option<PathBuf>,
+    /// Only accept a station whose name is exactly the one given, rather than falling back
+    /// to stations whose name contains it
+    #[arg(long, conflicts_with = "station_contains")]
+    station_exact: bool,
+    /// Match every station whose name contains the one given, ignoring case, even when one is
+    /// named exactly that; several matches are listed to choose from
+    #[arg(long)]
+    station_contains: bool,
+    /// Also accept the public stop code posted at the station, trying it before the name
+    #[arg(long)]
+    by_code: bool,
+    /// Print the feeds that would be fetched, the station and the options in effect, then exit
+    /// without touching the network
+    #[arg(long)]
+    dry_run: bool,
+    /// Print details about how the arguments were interpreted
+    #[arg(long, short)]
+    verbose: bool,
+    /// File holding the last successful results, shown when the feeds are unreachable
+    /// [default: $XDG_CACHE_HOME/train_display/<station>.tsv]
+    #[arg(long)]
+    fallback_file: Option<PathBuf>,
+    /// Agency whose feeds are used, among those listed by the `agencies` subcommand
+    #[arg(long, default_value = "exo", value_parser = parse_agency)]
+    agency: &'static Agency,
+    /// Read the static feed from a local zip or directory instead of downloading it
+    #[arg(long)]
+    static_path: Option<PathBuf>,
+    /// Keep a downloaded copy of the static feed, reused while younger than this many hours,
+    /// along with an index of it that spares parsing it again
+    #[arg(long, conflicts_with = "static_path")]
+    static_max_age: Option<u64>,
+    /// Read the realtime feed from a local protobuf file instead of downloading it
+    #[arg(long)]
+    realtime_path: Option<PathBuf>,
+    /// Realtime feed to use instead of the agency's; repeat it for agencies that split trip updates,
+    /// vehicle positions and alerts across several feeds. `<token>` is replaced by the token
+    #[arg(
+        long = "realtime-url",
+        value_name = "URL",
+        conflicts_with = "realtime_path"
+    )]
+    realtime_urls: Vec<String>,
+    /// Listen to realtime updates pushed as server-sent events by this URL, each event's data
+    /// being a base64-encoded feed message, instead of polling; `<token>` is replaced by the token
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["realtime_path", "realtime_urls"]
+    )]
+    realtime_stream: Option<String>,
+    /// Refuse realtime feeds larger than this many megabytes, in case the endpoint misbehaves
+    #[arg(long, value_name = "MB", default_value_t = 8)]
+    max_realtime_size: usize,
+    /// Send the requests for both feeds through this proxy, like `http://proxy.example:3128`,
+    /// instead of the one set by the HTTP_PROXY, HTTPS_PROXY and NO_PROXY variables
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+    /// Read the realtime API token from the first line of this file, which must not be
+    /// accessible to other users
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+    /// Refuse to show departures once the static feed has been expired for more than this many
+    /// days; an expired feed otherwise only prints a warning
+    #[arg(long)]
+    max_age_days: Option<i64>,
+    /// Also list trains that left up to this many minutes ago, marked as departed
+    #[arg(
+        long,
+        value_name = "MINUTES",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(i64).range(0..=MAX_MINUTES)
+    )]
+    include_past: i64,
+    /// List the trains leaving within this many hours, even past the end of the service day,
+    /// instead of those until the end of the service day at 02:00
+    #[arg(long, value_name = "HOURS", value_parser = clap::value_parser!(i64).range(1..=24))]
+    window: Option<i64>,
+    /// How departures are printed
+    #[arg(long, value_enum, default_value_t = Format::Human)]
+    format: Format,
+    /// Width of the `--format svg` image, in pixels
+    #[arg(long, value_name = "PIXELS", default_value_t = 480)]
+    svg_width: u32,
+    /// Height of the `--format svg` image, in pixels; departures that do not fit are left out
+    #[arg(long, value_name = "PIXELS", default_value_t = 320)]
+    svg_height: u32,
+    /// Print only the next departures on a single line, for status bars
+    #[arg(long, conflicts_with = "format")]
+    terse: bool,
+    /// Number of departures on the `--terse` line
+    #[arg(long, default_value_t = 2, requires = "terse")]
+    terse_count: usize,
+    /// Text between departures on the `--terse` line
+    #[arg(long, default_value = " → ", requires = "terse")]
+    terse_separator: String,
+    /// Print only how many minutes from now the next departures leave, like `4 12 25`, for
+    /// small LED boards
+    #[arg(long, conflicts_with_all = ["format", "terse"])]
+    compact_time: bool,
+    /// Number of countdowns printed by `--compact-time`, per headsign with `--per-direction`
+    #[arg(long, default_value_t = 3, requires = "compact_time")]
+    compact_time_count: usize,
+    /// Give `--compact-time` a line per headsign, like `Saint-Jerome: 4 25`
+    #[arg(long, requires = "compact_time")]
+    per_direction: bool,
+    /// Print only the next departure and how soon it leaves, failing when there is none
+    #[arg(long, conflicts_with_all = ["format", "terse", "compact_time", "sort"])]
+    next: bool,
+    /// List the trains arriving at the station, at their arrival time, instead of those leaving
+    #[arg(long, conflicts_with = "to")]
+    arrivals: bool,
+    /// Also list the trains riders cannot board at the station, noting what each one allows
+    #[arg(long, conflicts_with = "arrivals")]
+    include_no_pickup: bool,
+    /// Print only the last train of the service day, even if it already left, and how long
+    /// from now it leaves
+    #[arg(long, conflicts_with_all = ["format", "terse", "compact_time", "next", "sort", "explain"])]
+    last: bool,
+    /// Print only the first train of the next service day, as scheduled, and how long from now
+    /// it leaves
+    #[arg(long, conflicts_with_all = ["format", "terse", "compact_time", "next", "last", "sort", "explain"])]
+    first: bool,
+    /// List at most this many departures, counting only those left once every other filter,
+    /// `--to` included, has been applied; per station with `--group-by station`
+    #[arg(long, value_name = "COUNT")]
+    limit: Option<usize>,
+    /// File of per-route limits, one `route[, route...] = count` per line with routes given by
+    /// id or name, overriding `--limit` for those routes; `--limit` then caps each other route
+    /// instead of the board
+    #[arg(long, value_name = "FILE")]
+    route_limits: Option<PathBuf>,
+    /// On a terminal, print boards in the human format this many lines at a time, waiting for
+    /// Enter before the next ones; ignored in watch mode and when the output is not interactive
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..))]
+    departures_per_page: Option<u64>,
+    /// Only list the trains of this route, given by id or name; repeat for several routes
+    #[arg(long = "route", value_name = "ROUTE")]
+    routes: Vec<String>,
+    /// Only list this trip, by trip id; repeat for several trips
+    #[arg(long = "include-trip", value_name = "TRIP_ID")]
+    include_trips: Vec<String>,
+    /// Leave out this trip, by trip id, like one the feed gets wrong; repeat for several trips
+    #[arg(long = "exclude-trip", value_name = "TRIP_ID")]
+    exclude_trips: Vec<String>,
+    /// Order in which departures are listed
+    #[arg(long, value_enum, default_value_t = SortOrder::Time)]
+    sort: SortOrder,
+    /// Print a separator after the trains leaving within this many minutes
+    #[arg(
+        long,
+        value_name = "MINUTES",
+        num_args = 0..=1,
+        default_missing_value = "15",
+        value_parser = clap::value_parser!(i64).range(0..=MAX_MINUTES)
+    )]
+    separate_imminent: Option<i64>,
+    /// List only the trains the realtime feed has news about: a delay, a cancellation, a
+    /// platform change or the trainset running them
+    #[arg(long)]
+    only_realtime: bool,
+    /// Ignore the arrival delay of stop updates predicting no departure, instead of taking it as
+    /// a best-effort estimate of the departure delay
+    #[arg(long)]
+    strict_delays: bool,
+    /// Prefix approximate (non-timepoint or interpolated) times with `~`
+    #[arg(long)]
+    mark_approximate: bool,
+    /// When to color departures by delay
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+    /// Language of the labels of human output, from the locale by default; names from the
+    /// feed are printed as they are
+    #[arg(long, value_enum)]
+    lang: Option<Lang>,
+    /// Delay, in minutes, from which a departure is shown as slightly late
+    #[arg(long, default_value_t = 1)]
+    minor_delay: i64,
+    /// Delay, in minutes, from which a departure is shown as very late
+    #[arg(long, default_value_t = 5)]
+    major_delay: i64,
+    /// Note trains that continue as another trip past their terminus
+    #[arg(long)]
+    through_running: bool,
+    /// Group departures by headsign, listing each destination's times on one line
+    #[arg(long)]
+    compact_headsigns: bool,
+    /// Follow the departures with a line counting them, and the delayed and cancelled ones
+    #[arg(long)]
+    summary: bool,
+    /// Collapse consecutive cancelled trains into one line, like `3 trains cancelled
+    /// (17:42–18:10)`, so that the running trains stand out during disruptions
+    #[arg(long)]
+    compact_cancelled: bool,
+    /// Name trains by their number when the feed gives one, like `Train 28 to Gare Centrale`
+    #[arg(long)]
+    train_numbers: bool,
+    /// Keep running, refreshing the departures every this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    watch: Option<u64>,
+    /// In watch mode, also refresh as soon as the process receives SIGUSR1 (Unix only)
+    #[arg(long, requires = "watch")]
+    refresh_on_sigusr1: bool,
+    /// In watch mode, wait up to this many more seconds, at random, between refreshes so that
+    /// several displays don't poll the feeds in step
+    #[arg(long, value_name = "SECONDS", requires = "watch", default_value_t = 0)]
+    poll_jitter: u64,
+    /// Instead of the departures, explain how this trip is handled at the station
+    #[arg(long, value_name = "TRIP_ID")]
+    explain: Option<String>,
+    /// Compute departures as if it were this RFC 3339 time instead of now
+    #[arg(long, value_name = "TIME", value_parser = DateTime::parse_from_rfc3339)]
+    now: Option<DateTime<FixedOffset>>,
+    /// Replay the realtime feeds captured in this directory instead of fetching them: each
+    /// watch refresh advances the clock by the interval, from `--now` or the first capture,
+    /// and uses the latest capture by header timestamp
+    #[arg(long, value_name = "DIR", requires = "watch", conflicts_with_all = ["realtime_path", "realtime_urls"])]
+    as_of: Option<PathBuf>,
+    /// Only list trains that then stop at this station; give it twice, connecting station
+    /// first, to list each train with the next one it connects to
+    #[arg(long, value_name = "STATION")]
+    to: Vec<String>,
+    /// Mark the trains that then stop at this station, still listing the others
+    #[arg(long, value_name = "STATION")]
+    highlight: Option<String>,
+    /// Minutes it takes to walk to the platform: trains leaving sooner are left out, and the
+    /// first one that can still be caught is marked
+    #[arg(long, value_name = "MINUTES", value_parser = clap::value_parser!(i64).range(0..=MAX_MINUTES))]
+    walk_time: Option<i64>,
+    /// With `--walk-time`, how many minutes before departure the doors close
+    #[arg(
+        long,
+        value_name = "MINUTES",
+        requires = "walk_time",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(i64).range(0..=MAX_MINUTES)
+    )]
+    boarding_buffer: i64,
+    /// Minimum time, in minutes, to change trains at the connecting station, unless the feed's
+    /// transfers.txt has a rule for the two platforms
+    #[arg(long, value_name = "MINUTES", default_value_t = 5, value_parser = clap::value_parser!(i64).range(0..=MAX_MINUTES))]
+    min_connection: i64,
+    /// Note how many stops each train makes before reaching the `--to` station
+    #[arg(long, requires = "to")]
+    stop_count: bool,
+    /// Write the output to this file instead of stdout, replacing it atomically so readers
+    /// never see a partial write
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// strftime pattern for the displayed times; JSON output always uses RFC 3339
+    #[arg(long, value_name = "PATTERN", default_value = "%H:%M", value_parser = parse_time_format)]
+    timefmt: String,
+    /// Fields to print for each departure, in this order, instead of the default layout
+    #[arg(long, value_enum, value_delimiter = ',')]
+    columns: Vec<Column>,
+    /// Rate each realtime prediction as high, medium or low confidence, from its uncertainty
+    /// and the age of the realtime feed
+    #[arg(long)]
+    confidence: bool,
+    /// Debugging aid: follow each departure with the delay, in seconds, read from the realtime
+    /// feed and the stop update it was taken from
+    #[arg(long)]
+    raw_delay: bool,
+    /// Debugging aid: keep the trips of yesterday's service that belong to an earlier service
+    /// day, and follow each departure with the service day it was expanded from
+    #[arg(long, hide = true)]
+    no_dedup_dates: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that both feeds can be fetched and decoded, exiting nonzero otherwise
+    Healthcheck,
+    /// List the agencies that can be given to --agency, and their feeds
+    Agencies,
+    /// Print what the feeds contain: the static feed's publisher, version and validity, how
+    /// many agencies, routes, trips and stops it has, and the realtime feed's header
+    FeedInfo,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    /// One readable line per departure
+    Human,
+    /// One JSON object per line, for log ingestion pipelines
+    Ndjson,
+    /// A single JSON document with a schema version and the departures
+    Json,
+    /// An SVG image of a departure board, for dashboards and e-ink displays
+    Svg,
+}
+
+/// A field of the human output, as selected by `--columns`.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Column {
+    /// Departure time, including the realtime delay
+    Time,
+    /// Minutes until departure
+    Countdown,
+    /// Route name, or id when the feed gives it no name
+    Route,
+    Headsign,
+    /// Train number, when the feed gives one
+    Train,
+    /// Platform or track, when the feed gives one
+    Platform,
+    /// Realtime delay, in minutes
+    Delay,
+    /// Arrival time at the `--to` station
+    Arrival,
+    /// Number of stops before the `--to` station
+    Stops,
+    /// Confidence in the realtime prediction
+    Confidence,
+    /// Cancelled, departed or extra train, or what riders cannot do there
+    Status,
+    /// GTFS service day the trip runs on, which is the day before for trains past midnight
+    ServiceDay,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ColorMode {
+    /// Color when printing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// Which trains a station board lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Board {
+    /// Trains passengers can board at the station, at their departure time
+    Departures,
+    /// Trains passengers can leave at the station, at their arrival time
+    Arrivals,
+    /// Every train calling at or passing through the station, at its departure time
+    AllCalls,
+}
+
+impl Board {
+    /// Whether this board lists the train calling at `stop_time`.
+    fn lists(self, stop_time: &StopTime) -> bool {
+        match self {
+            Board::Departures => stop_time.pickup_type != PickupDropOffType::NotAvailable,
+            Board::Arrivals => stop_time.drop_off_type != PickupDropOffType::NotAvailable,
+            Board::AllCalls => true,
+        }
+    }
+
+    /// What riders cannot do at `stop_time` although the board lists it, if anything.
+    fn restriction(self, stop_time: &StopTime) -> Option<&'static str> {
+        let no_pickup = stop_time.pickup_type == PickupDropOffType::NotAvailable;
+        let no_drop_off = stop_time.drop_off_type == PickupDropOffType::NotAvailable;
+        match self {
+            Board::AllCalls if no_pickup && no_drop_off => Some("no pickup or drop-off"),
+            Board::AllCalls if no_pickup => Some("drop-off only"),
+            Board::AllCalls if no_drop_off => Some("pickup only"),
+            Board::Departures if stop_time.drop_off_type == PickupDropOffType::NotAvailable => {
+                Some("pickup only")
+            }
+            Board::Arrivals if stop_time.pickup_type == PickupDropOffType::NotAvailable => {
+                Some("drop-off only")
+            }
+            _ => None,
+        }
+    }
+
+    /// The time this board shows for `trip.stop_times[index]`, in seconds after midnight,
+    /// and whether it is only approximate.
+    fn scheduled_time(self, trip: &Trip, index: usize) -> Option<(u32, bool)> {
+        let stop_time = &trip.stop_times[index];
+        match (self, stop_time.arrival_time) {
+            (Board::Arrivals, Some(seconds)) => {
+                Some((seconds, stop_time.timepoint == TimepointType::Approximate))
+            }
+            _ => scheduled_departure(trip, index),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum GroupBy {
+    /// One board per station given on the command line
+    Station,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SortOrder {
+    /// Earliest departure first
+    Time,
+    /// Grouped by route, then by time
+    Route,
+    /// Most delayed first, then by time
+    Delay,
+}
+
+#[derive(Clone, Debug)]
+pub struct Departure {
+    trip_id: String,
+    route_id: String,
+    /// What riders call the route, from its short or long name in the static feed
+    route_name: Option<String>,
+    /// Expected departure time: the scheduled time with the realtime delay already applied
+    time: DateTime<Tz>,
+    /// Realtime delay in seconds, if the feed predicted one
+    delay: Option<i32>,
+    headsign: String,
+    /// Number riders know the train by, from the trip's short name, like `28`
+    train_number: Option<String>,
+    /// Extra train announced by the realtime feed, absent from the static schedule
+    extra: bool,
+    /// The scheduled time is not an exact timepoint, or was interpolated between timepoints
+    approximate: bool,
+    /// Block of trips run one after the other by the same vehicle
+    block_id: Option<String>,
+    /// Headsign of the next trip in the block, when the train runs through its terminus
+    continues_as: Option<String>,
+    /// The realtime feed announced the trip as cancelled
+    cancelled: bool,
+    /// Platform or track code of the stop, from the static feed
+    platform: Option<String>,
+    /// Platform the realtime feed moved the train to, when it differs from `platform`
+    platform_change: Option<String>,
+    /// What riders cannot do at the station on this train, like `pickup only`
+    restriction: Option<&'static str>,
+    /// Label or id of the trainset running the trip, according to the realtime feed
+    vehicle: Option<String>,
+    /// Arrival time at the `--to` station, assuming the delay at departure holds
+    arrival: Option<DateTime<Tz>>,
+    /// Stops made between the station and the `--to` station
+    intermediate_stops: Option<usize>,
+    /// The train stops at the `--highlight` station afterwards
+    highlighted: bool,
+    /// Whether the train can still be caught given `--walk-time`, when it is set
+    reachable: Option<bool>,
+    /// The earliest running train that can still be caught given `--walk-time`
+    first_reachable: bool,
+    /// Uncertainty of the realtime delay in seconds, when the feed gives one
+    uncertainty: Option<i32>,
+    /// The realtime stop update the delay was read from, like `VM #2`
+    delay_source: Option<String>,
+    /// Id of the realtime feed entity updating the trip
+    realtime_entity: Option<String>,
+    /// The stop of the station the train calls at
+    stop_id: Option<String>,
+    /// GTFS service day the trip runs on, whose calendar matched: yesterday's for a train
+    /// past midnight. Unknown for extra trains the feed gives no start date
+    service_date: Option<NaiveDate>,
+    /// How the delay compares to the previous refresh in watch mode, `Greater` when it grew
+    trend: Option<Ordering>,
+}
+
+impl Departure {
+    /// A plain scheduled departure; the remaining fields start out unset.
+    fn new(trip_id: String, route_id: String, time: DateTime<Tz>, headsign: String) -> Self {
+        Departure {
+            trip_id,
+            route_id,
+            route_name: None,
+            time,
+            delay: None,
+            headsign,
+            train_number: None,
+            extra: false,
+            approximate: false,
+            block_id: None,
+            continues_as: None,
+            cancelled: false,
+            platform: None,
+            platform_change: None,
+            restriction: None,
+            vehicle: None,
+            arrival: None,
+            intermediate_stops: None,
+            highlighted: false,
+            reachable: None,
+            first_reachable: false,
+            uncertainty: None,
+            delay_source: None,
+            realtime_entity: None,
+            stop_id: None,
+            service_date: None,
+            trend: None,
+        }
+    }
+
+    /// Whether the departure is on `route`, given by id or, ignoring case, by name.
+    fn is_on(&self, route: &str) -> bool {
+        self.route_id == route
+            || self
+                .route_name
+                .as_ref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(route))
+    }
+
+    /// Whether anything about the departure comes from the realtime feed.
+    fn has_realtime(&self) -> bool {
+        self.delay.is_some()
+            || self.extra
+            || self.cancelled
+            || self.platform_change.is_some()
+            || self.vehicle.is_some()
+    }
+
+    /// All the fields of the departure as a JSON object, with times in RFC 3339, for the
+    /// formats to pick from.
+    fn to_json_value(&self) -> json::Value {
+        json::Value::Object(vec![
+            ("trip_id".into(), self.trip_id.as_str().into()),
+            ("route_id".into(), self.route_id.as_str().into()),
+            ("route_name".into(), self.route_name.as_deref().into()),
+            ("time".into(), self.time.to_rfc3339().into()),
+            ("delay".into(), self.delay.into()),
+            ("headsign".into(), self.headsign.as_str().into()),
+            ("train_number".into(), self.train_number.as_deref().into()),
+            ("extra".into(), self.extra.into()),
+            ("cancelled".into(), self.cancelled.into()),
+            ("approximate".into(), self.approximate.into()),
+            ("block_id".into(), self.block_id.as_deref().into()),
+            ("continues_as".into(), self.continues_as.as_deref().into()),
+            ("platform".into(), self.platform.as_deref().into()),
+            (
+                "platform_changed".into(),
+                self.platform_change.is_some().into(),
+            ),
+            (
+                "new_platform".into(),
+                self.platform_change.as_deref().into(),
+            ),
+            ("restriction".into(), self.restriction.into()),
+            ("vehicle".into(), self.vehicle.as_deref().into()),
+            (
+                "arrival".into(),
+                self.arrival.map(|arrival| arrival.to_rfc3339()).into(),
+            ),
+            (
+                "intermediate_stops".into(),
+                self.intermediate_stops
+                    .and_then(|stops| i64::try_from(stops).ok())
+                    .into(),
+            ),
+            ("highlighted".into(), self.highlighted.into()),
+            ("reachable".into(), self.reachable.into()),
+            ("first_reachable".into(), self.first_reachable.into()),
+            ("uncertainty".into(), self.uncertainty.into()),
+            ("delay_source".into(), self.delay_source.as_deref().into()),
+            (
+                "realtime_entity".into(),
+                self.realtime_entity.as_deref().into(),
+            ),
+            ("stop_id".into(), self.stop_id.as_deref().into()),
+            (
+                "service_date".into(),
+                self.service_date.map(|date| date.to_string()).into(),
+            ),
+            (
+                "delay_trend".into(),
+                self.trend
+                    .map(|trend| match trend {
+                        Ordering::Greater => "growing",
+                        Ordering::Less => "shrinking",
+                        Ordering::Equal => "steady",
+                    })
+                    .into(),
+            ),
+        ])
+    }
+}
+
+/// Each trip's delay at the last refresh in watch mode, by trip id and service date.
+type DelayHistory = HashMap<(String, NaiveDate), i32>;
+
+/// What watch mode carries over from one refresh to the next.
+#[derive(Default)]
+struct WatchState {
+    delays: DelayHistory,
+    /// Realtime feeds to apply the next DIFFERENTIAL messages to
+    snapshots: feed::Snapshots,
+    /// The static feed, and when it was loaded
+    static_feed: Option<(Instant, Arc<Gtfs>)>,
+    /// Names of the stations on the command line as resolved in the feed, once resolved
+    stations: Option<Vec<String>>,
+}
+
+/// How long watch mode keeps the static feed it loaded before loading it again. Timetables
+/// change a few times a year, unlike the realtime feed fetched on every refresh.
+const STATIC_REFRESH: Duration = Duration::from_secs(6 * 3600);
+
+/// The static feed from `source`, loaded once per [`STATIC_REFRESH`] when watch mode gives its
+/// `cache`, and on every call otherwise. A cached feed is kept when it cannot be loaded again.
+async fn static_feed(
+    client: &Client,
+    source: &FeedSource,
+    cache: Option<&mut Option<(Instant, Arc<Gtfs>)>>,
+) -> anyhow::Result<Arc<Gtfs>> {
+    let Some(cache) = cache else {
+        return Ok(Arc::new(feed::fetch_static(client, source).await?));
+    };
+    if let Some((loaded, gtfs)) = cache
+        && loaded.elapsed() < STATIC_REFRESH
+    {
+        return Ok(Arc::clone(gtfs));
+    }
+    match (feed::fetch_static(client, source).await, cache) {
+        (Ok(gtfs), cache) => {
+            let gtfs = Arc::new(gtfs);
+            *cache = Some((Instant::now(), Arc::clone(&gtfs)));
+            Ok(gtfs)
+        }
+        (Err(err), Some((_loaded, gtfs))) => {
+            eprintln!("WARNING: Keeping the static feed loaded earlier: {err:#}");
+            Ok(Arc::clone(gtfs))
+        }
+        (Err(err), None) => Err(err),
+    }
+}
+
+/// Sets the delay trend of `departures` from the delays in `history`, then replaces `history`
+/// with their current delays. Trips seen for the first time get no trend.
+fn note_delay_trends(history: &mut DelayHistory, departures: &mut [Departure]) {
+    let mut current = DelayHistory::new();
+    for departure in departures {
+        let (Some(date), Some(delay)) = (departure.service_date, departure.delay) else {
+            continue;
+        };
+        let key = (departure.trip_id.clone(), date);
+        departure.trend = history.get(&key).map(|previous| delay.cmp(previous));
+        current.insert(key, delay);
+    }
+    *history = current;
+}
+
+/// Default location of the last-results file for a station, under the user's cache directory.
+fn default_fallback_file(station_name: &str) -> Option<PathBuf> {
+    let file_name = station_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    Some(feed::cache_dir()?.join(format!("{file_name}.tsv")))
+}
+
+/// Writes the computed departures, preceded by the capture timestamp, as tab-separated lines.
+fn save_departures(
+    path: &Path,
+    captured: DateTime<Utc>,
+    departures: &[Departure],
+) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut contents = format!("{}\n", captured.to_rfc3339());
+    for departure in departures {
+        contents.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            departure.time.to_rfc3339(),
+            departure.trip_id,
+            departure.route_id,
+            departure
+                .delay
+                .map(|delay| delay.to_string())
+                .unwrap_or_default(),
+            departure.extra,
+            departure.approximate,
+            departure.cancelled,
+            departure.continues_as.as_deref().unwrap_or_default(),
+            departure.platform.as_deref().unwrap_or_default(),
+            departure.platform_change.as_deref().unwrap_or_default(),
+            departure.vehicle.as_deref().unwrap_or_default(),
+            departure
+                .arrival
+                .map(|arrival| arrival.to_rfc3339())
+                .unwrap_or_default(),
+            departure
+                .intermediate_stops
+                .map(|stops| stops.to_string())
+                .unwrap_or_default(),
+            departure
+                .uncertainty
+                .map(|uncertainty| uncertainty.to_string())
+                .unwrap_or_default(),
+            departure.stop_id.as_deref().unwrap_or_default(),
+            departure.train_number.as_deref().unwrap_or_default(),
+            departure.route_name.as_deref().unwrap_or_default(),
+            departure
+                .service_date
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            departure.headsign
+        ));
+    }
+    std::fs::write(path, contents).with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Reads back a file written by [`save_departures`].
+fn load_departures(path: &Path) -> anyhow::Result<(DateTime<Utc>, Vec<Departure>)> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    let mut lines = contents.lines();
+    let captured = DateTime::parse_from_rfc3339(lines.next().unwrap_or_default())
+        .with_context(|| format!("Invalid capture timestamp in {}", path.display()))?
+        .with_timezone(&Utc);
+    let departures = lines
+        .map(|line| {
+            let fields = line.splitn(19, '\t').collect::<Vec<_>>();
+            let [
+                time,
+                trip_id,
+                route_id,
+                delay,
+                extra,
+                approximate,
+                cancelled,
+                continues_as,
+                platform,
+                platform_change,
+                vehicle,
+                arrival,
+                intermediate_stops,
+                uncertainty,
+                stop_id,
+                train_number,
+                route_name,
+                service_date,
+                headsign,
+            ] = fields[..]
+            else {
+                bail!("Malformed line in {}: {line}", path.display());
+            };
+            Ok(Departure {
+                delay: (!delay.is_empty()).then(|| delay.parse()).transpose()?,
+                extra: extra.parse()?,
+                approximate: approximate.parse()?,
+                cancelled: cancelled.parse()?,
+                continues_as: (!continues_as.is_empty()).then(|| continues_as.to_owned()),
+                platform: (!platform.is_empty()).then(|| platform.to_owned()),
+                platform_change: (!platform_change.is_empty()).then(|| platform_change.to_owned()),
+                vehicle: (!vehicle.is_empty()).then(|| vehicle.to_owned()),
+                arrival: (!arrival.is_empty())
+                    .then(|| DateTime::parse_from_rfc3339(arrival))
+                    .transpose()?
+                    .map(|arrival| arrival.with_timezone(&Eastern)),
+                intermediate_stops: (!intermediate_stops.is_empty())
+                    .then(|| intermediate_stops.parse())
+                    .transpose()?,
+                uncertainty: (!uncertainty.is_empty())
+                    .then(|| uncertainty.parse())
+                    .transpose()?,
+                stop_id: (!stop_id.is_empty()).then(|| stop_id.to_owned()),
+                train_number: (!train_number.is_empty()).then(|| train_number.to_owned()),
+                route_name: (!route_name.is_empty()).then(|| route_name.to_owned()),
+                service_date: (!service_date.is_empty())
+                    .then(|| service_date.parse())
+                    .transpose()?,
+                ..Departure::new(
+                    trip_id.to_owned(),
+                    route_id.to_owned(),
+                    DateTime::parse_from_rfc3339(time)?.with_timezone(&Eastern),
+                    headsign.to_owned(),
+                )
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok((captured, departures))
+}
+
+/// Checks that `--timefmt` is a valid strftime pattern, so that formatting can't fail later.
+fn parse_time_format(pattern: &str) -> anyhow::Result<String> {
+    if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+        bail!("`{pattern}` is not a valid strftime pattern");
+    }
+    Ok(pattern.to_owned())
+}
+
+/// The output format selected on the command line.
+fn output_format(args: &Cli) -> Box<dyn OutputFormat> {
+    if args.next {
+        return Box::new(output::Terse {
+            count: 1,
+            separator: String::new(),
+            countdown: true,
+        });
+    }
+    if args.terse {
+        return Box::new(output::Terse {
+            count: args.terse_count,
+            separator: args.terse_separator.clone(),
+            countdown: false,
+        });
+    }
+    if args.compact_time {
+        return Box::new(output::Countdowns {
+            count: args.compact_time_count,
+            per_headsign: args.per_direction,
+        });
+    }
+    match args.format {
+        Format::Human => Box::new(output::Human {
+            mark_approximate: args.mark_approximate,
+            minor_delay: args.minor_delay,
+            major_delay: args.major_delay,
+            through_running: args.through_running,
+            separate_imminent: args.separate_imminent,
+            columns: args.columns.clone(),
+            compact_headsigns: args.compact_headsigns,
+            stop_count: args.stop_count,
+            raw_delay: args.raw_delay,
+            service_dates: args.no_dedup_dates,
+            confidence: args.confidence,
+            summary: args.summary,
+            compact_cancelled: args.compact_cancelled,
+            train_numbers: args.train_numbers,
+            highlight: args.highlight.clone(),
+        }),
+        Format::Ndjson => Box::new(output::Ndjson),
+        Format::Json => Box::new(output::Json),
+        Format::Svg => Box::new(output::Svg {
+            width: args.svg_width,
+            height: args.svg_height,
+            minor_delay: args.minor_delay,
+            major_delay: args.major_delay,
+        }),
+    }
+}
+
+/// Replaces `path` with `contents` by writing a temporary file next to it and renaming it over
+/// `path`, so that readers see either the old or the new contents in full.
+fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    std::fs::write(&temp_path, contents)
+        .with_context(|| format!("Could not write {}", temp_path.display()))?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("Could not replace {}", path.display()))
+}
+
+/// Prints departures in the format selected on the command line, to `--output` if given.
+///
+/// With `--group-by station`, each of `stations`, given by name and stop ids, gets its own
+/// board under a header; when `stations` is empty, all departures are listed together.
+/// In the human and JSON formats, each board starts with `notes`; other formats print them to
+/// stderr. The limits, see [`within_limits`], apply to each board once `departures` have been
+/// filtered and sorted. With `--departures-per-page` on a terminal outside watch mode, each board in the
+/// human format is then printed a page of lines at a time.
+fn print_output(
+    departures: &[Departure],
+    stations: &[(String, Vec<String>)],
+    now: DateTime<Tz>,
+    feed_age: Option<TimeDelta>,
+    notes: &[Note],
+    args: &Cli,
+) -> anyhow::Result<()> {
+    let lang = args.lang.unwrap_or_else(Lang::from_env);
+    let notes = match args.format {
+        Format::Human | Format::Json | Format::Ndjson
+            if !args.terse && !args.compact_time && !args.next =>
+        {
+            notes
+        }
+        _ => {
+            for note in notes {
+                eprintln!("{}", note.text(Lang::En));
+            }
+            &[]
+        }
+    };
+    let context = |station| RenderContext {
+        now,
+        feed_age,
+        station,
+        time_format: &args.timefmt,
+        lang,
+        notes,
+        color: match args.color {
+            ColorMode::Auto => args.output.is_none() && std::io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        },
+    };
+    let format = output_format(args);
+    let route_limits = match &args.route_limits {
+        Some(path) => load_route_limits(path)?,
+        None => HashMap::new(),
+    };
+    let interactive =
+        args.output.is_none() && std::io::stdout().is_terminal() && std::io::stdin().is_terminal();
+    let per_page = args
+        .departures_per_page
+        // Watch mode must keep listening for Ctrl-C rather than wait on stdin.
+        .filter(|_| interactive && args.watch.is_none() && matches!(args.format, Format::Human))
+        .and_then(|count| usize::try_from(count).ok())
+        .unwrap_or(usize::MAX);
+    // Rendered whole, as summaries and groupings look at every departure of the board.
+    let render_pages = |departures: &[Departure], ctx: &RenderContext| {
+        into_pages(format.render(departures, ctx), per_page)
+    };
+    let pages = match args.group_by {
+        Some(GroupBy::Station) if !stations.is_empty() => {
+            let mut pages = Vec::new();
+            for (name, stop_ids) in stations {
+                let departures = departures.iter().filter(|departure| {
+                    departure
+                        .stop_id
+                        .as_ref()
+                        .is_some_and(|stop_id| stop_ids.contains(stop_id))
+                });
+                let departures = within_limits(departures, &route_limits, args.limit);
+                let mut board = render_pages(&departures, &context(name));
+                // Terse lines already start with the station.
+                if !args.terse {
+                    board[0].insert_str(0, &format!("== {name} ==\n"));
+                }
+                pages.append(&mut board);
+            }
+            pages
+        }
+        _ => render_pages(
+            &within_limits(departures.iter(), &route_limits, args.limit),
+            &context(args.station.as_deref().unwrap_or_default()),
+        ),
+    };
+    if let Some(path) = &args.output {
+        return write_atomically(path, pages.concat().as_bytes());
+    }
+    for (index, page) in pages.iter().enumerate() {
+        let mut stdout = std::io::stdout().lock();
+        // A closed pipe just means nobody is listening anymore.
+        let _ = stdout
+            .write_all(page.as_bytes())
+            .and_then(|()| stdout.flush());
+        if index + 1 < pages.len() {
+            eprint!("{}", lang.tr("-- more --"));
+            let mut answer = String::new();
+            if std::io::stdin().read_line(&mut answer)? == 0 {
+                break;
+            }
+            // Erase the prompt, which Enter left on the line above.
+            eprint!("\x1b[1A\x1b[2K");
+        }
+    }
+    Ok(())
+}
+
+/// `board` cut into pages of `per_page` lines, the last one possibly shorter.
+fn into_pages(board: String, per_page: usize) -> Vec<String> {
+    let lines = board.split_inclusive('\n').collect::<Vec<_>>();
+    if lines.len() <= per_page {
+        return vec![board];
+    }
+    lines.chunks(per_page).map(<[&str]>::concat).collect()
+}
+
+/// The first of `departures` up to `limit`, or when `route_limits` is set, up to the limit of
+/// each route: its own in `route_limits`, `limit` otherwise.
+fn within_limits<'a>(
+    departures: impl Iterator<Item = &'a Departure>,
+    route_limits: &HashMap<String, usize>,
+    limit: Option<usize>,
+) -> Vec<Departure> {
+    if route_limits.is_empty() {
+        return departures
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+    }
+    let mut listed = HashMap::new();
+    departures
+        .filter(|departure| {
+            let count = listed.entry(departure.route_id.as_str()).or_insert(0);
+            *count += 1;
+            let cap = route_limits
+                .iter()
+                .find(|(route, _limit)| departure.is_on(route))
+                .map(|(_route, limit)| *limit)
+                .or(limit);
+            cap.is_none_or(|cap| *count <= cap)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Display name of each route of `gtfs` that has one, its short name or else its long name,
+/// by route id.
+fn route_names(gtfs: &Gtfs) -> HashMap<&str, &str> {
+    gtfs.routes
+        .values()
+        .filter_map(|route| {
+            let name = [&route.short_name, &route.long_name]
+                .into_iter()
+                .flatten()
+                .find(|name| !name.is_empty())?;
+            Some((route.id.as_str(), name.as_str()))
+        })
+        .collect()
+}
+
+/// With `--walk-time`, drops the trains leaving too soon to be caught from `now`, counting the
+/// walk to the platform then the `--boarding-buffer` before departure, and marks the earliest
+/// one left that runs. Trains already gone stay, for `--include-past` to list.
+fn keep_reachable(departures: &mut Vec<Departure>, now: DateTime<Tz>, args: &Cli) {
+    let Some(walk_time) = args.walk_time else {
+        return;
+    };
+    let catchable_from =
+        now + TimeDelta::minutes(walk_time) + TimeDelta::minutes(args.boarding_buffer);
+    departures.retain_mut(|departure| {
+        let reachable = departure.time >= catchable_from;
+        departure.reachable = Some(reachable);
+        reachable || departure.time < now
+    });
+    if let Some(first) = departures
+        .iter_mut()
+        .filter(|departure| departure.reachable == Some(true) && !departure.cancelled)
+        .min_by_key(|departure| departure.time)
+    {
+        first.first_reachable = true;
+    }
+}
+
+/// Whether `departure` is on one of `routes`, as given to `--route`, or there are none.
+fn on_routes(departure: &Departure, routes: &[String]) -> bool {
+    routes.is_empty() || routes.iter().any(|route| departure.is_on(route))
+}
+
+/// Whether the trip of `departure` is among the `--include-trip` ones, when there are any, and
+/// not among the `--exclude-trip` ones.
+fn trip_selected(departure: &Departure, args: &Cli) -> bool {
+    (args.include_trips.is_empty() || args.include_trips.contains(&departure.trip_id))
+        && !args.exclude_trips.contains(&departure.trip_id)
+}
+
+/// Parses an `--agency` value into one of the built-in agencies.
+fn parse_agency(name: &str) -> Result<&'static Agency, String> {
+    feed::AGENCIES
+        .iter()
+        .find(|agency| agency.name == name)
+        .ok_or_else(|| {
+            let names = feed::AGENCIES
+                .iter()
+                .map(|agency| agency.name)
+                .collect::<Vec<_>>();
+            format!("unknown agency; expected one of: {}", names.join(", "))
+        })
+}
+
+/// Prints the built-in agencies and their feeds, for the `agencies` subcommand.
+fn print_agencies() {
+    for agency in feed::AGENCIES {
+        println!("{}: {}", agency.name, agency.description);
+        println!("  static:   {}", agency.static_url);
+        println!(
+            "  realtime: {}{}",
+            agency.realtime_url,
+            if agency.requires_token() {
+                " (token required, see --token-file)"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// Reads the API token from the first line of `path`, refusing files other users can read.
+fn read_token_file(path: &Path) -> anyhow::Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(path)
+            .with_context(|| format!("Could not read token file {}", path.display()))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            bail!(
+                "Token file {} is accessible by other users (mode {:o}); run `chmod 600` on it",
+                path.display(),
+                mode & 0o777
+            );
+        }
+    }
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read token file {}", path.display()))?;
+    let token = contents.lines().next().unwrap_or_default().trim();
+    if token.is_empty() {
+        bail!("Token file {} is empty", path.display());
+    }
+    Ok(token.to_owned())
+}
+
+/// The name of the station with a stop whose public `stop_code` is `code`. Nameless stops,
+/// like some entrances and boarding areas, can share the code of the station they belong to
+/// without naming it.
+fn station_with_code(gtfs: &Gtfs, code: &str) -> Option<String> {
+    gtfs.stops
+        .values()
+        .filter(|stop| stop.code.as_deref() == Some(code))
+        .find_map(|stop| stop.name.clone())
+}
+
+fn stop_ids_named(gtfs: &Gtfs, station_name: &str) -> Vec<String> {
+    gtfs.stops
+        .iter()
+        .filter(|(_id, stop)| stop.name.as_deref() == Some(station_name))
+        .map(|(id, _stop)| id.into())
+        .collect()
+}
+
+/// Resolves a partial station name to a single station, asking the user to choose when
+/// several stations match and stdin is a terminal.
+fn disambiguate_station(gtfs: &Gtfs, query: &str) -> anyhow::Result<String> {
+    let needle = query.to_lowercase();
+    let matches = gtfs
+        .stops
+        .values()
+        .filter_map(|stop| stop.name.as_ref())
+        .filter(|name| name.to_lowercase().contains(&needle))
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    match matches.as_slice() {
+        [] => bail!("Station name not found!"),
+        [name] => return Ok(name.clone()),
+        _ => (),
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "Several stations match `{query}`:\n  {}",
+            matches.join("\n  ")
+        );
+    }
+    eprintln!("Several stations match `{query}`:");
+    for (index, name) in matches.iter().enumerate() {
+        eprintln!("  {}) {name}", index + 1);
+    }
+    loop {
+        eprint!("Choose a station [1-{}]: ", matches.len());
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            bail!("No station chosen");
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(choice) if (1..=matches.len()).contains(&choice) => {
+                return Ok(matches[choice - 1].clone());
+            }
+            _ => eprintln!("Please enter a number between 1 and {}", matches.len()),
+        }
+    }
+}
+
+/// Parses an alias file into a map from alias to station name.
+///
+/// Each non-empty line has the form `gc, centrale = "Gare Centrale"`; `#` starts a comment.
+fn load_aliases(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    load_key_values(
+        path,
+        "alias file",
+        "alias",
+        "`alias = \"Station\"`",
+        |station| {
+            let station = station.trim_matches('"').trim();
+            if station.is_empty() {
+                return Err("empty station name".to_owned());
+            }
+            Ok(station.to_owned())
+        },
+    )
+}
+
+/// Parses a route limits file into a map from route id or name to the number of departures
+/// to list.
+///
+/// Each non-empty line has the form `r11, r12 = 3`; `#` starts a comment.
+fn load_route_limits(path: &Path) -> anyhow::Result<HashMap<String, usize>> {
+    load_key_values(
+        path,
+        "route limits file",
+        "route",
+        "`route = count`",
+        |limit| {
+            limit
+                .parse()
+                .map_err(|err| format!("invalid count ({err})"))
+        },
+    )
+}
+
+/// Parses a file whose non-empty lines have the form `key, other key = value`, where `#`
+/// starts a comment, into a map from each key to its value as read by `parse`.
+///
+/// Errors call the file `file` and its keys `key`, and give `syntax` as the expected form of
+/// a line.
+fn load_key_values<T: Clone>(
+    path: &Path,
+    file: &str,
+    key: &str,
+    syntax: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> anyhow::Result<HashMap<String, T>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {file} {}", path.display()))?;
+    let mut values = HashMap::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line
+            .split_once('#')
+            .map_or(line, |(before, _comment)| before);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let location = format!("{}:{}", path.display(), line_number + 1);
+        let Some((keys, value)) = line.split_once('=') else {
+            bail!("{location}: expected {syntax}");
+        };
+        let value = match parse(value.trim()) {
+            Ok(value) => value,
+            Err(err) => bail!("{location}: {err}"),
+        };
+        for name in keys.split(',').map(str::trim) {
+            if name.is_empty() {
+                bail!("{location}: empty {key}");
+            }
+            if values.insert(name.to_owned(), value.clone()).is_some() {
+                bail!("{location}: {key} `{name}` given twice");
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Why a service runs on a date or not, see [`service_day`].
+enum ServiceDay<'a> {
+    /// calendar_dates adds or removes the date
+    Exception(Exception),
+    /// Neither calendar_dates nor calendar has the service
+    NoCalendar,
+    /// The date is outside the range of the service's calendar
+    OutsideCalendar(&'a Calendar),
+    /// The service's calendar runs, or not, on the date's weekday
+    Weekday(&'a Calendar, bool),
+}
+
+impl ServiceDay<'_> {
+    fn runs(&self) -> bool {
+        matches!(
+            self,
+            ServiceDay::Exception(Exception::Added) | ServiceDay::Weekday(_, true)
+        )
+    }
+}
+
+/// Whether and why `service_id` runs on `date`: the last calendar_dates entry for the date
+/// decides, then the calendar's range and weekdays.
+fn service_day<'a>(gtfs: &'a Gtfs, service_id: &str, date: NaiveDate) -> ServiceDay<'a> {
+    let exception = gtfs.calendar_dates.get(service_id).and_then(|dates| {
+        dates
+            .iter()
+            .rev()
+            .find(|calendar_date| calendar_date.date == date)
+    });
+    if let Some(exception) = exception {
+        return ServiceDay::Exception(exception.exception_type);
+    }
+    let Some(calendar) = gtfs.calendar.get(service_id) else {
+        return ServiceDay::NoCalendar;
+    };
+    if date < calendar.start_date || calendar.end_date < date {
+        return ServiceDay::OutsideCalendar(calendar);
+    }
+    let runs = match date.weekday() {
+        Weekday::Mon => calendar.monday,
+        Weekday::Tue => calendar.tuesday,
+        Weekday::Wed => calendar.wednesday,
+        Weekday::Thu => calendar.thursday,
+        Weekday::Fri => calendar.friday,
+        Weekday::Sat => calendar.saturday,
+        Weekday::Sun => calendar.sunday,
+    };
+    ServiceDay::Weekday(calendar, runs)
+}
+
+/// Whether `service_id` runs on `date`, and why, as [`service_day`] decides.
+fn service_reason(gtfs: &Gtfs, service_id: &str, date: NaiveDate) -> (bool, String) {
+    let day = service_day(gtfs, service_id, date);
+    let range = |calendar: &Calendar| format!("{} to {}", calendar.start_date, calendar.end_date);
+    let reason = match day {
+        ServiceDay::Exception(Exception::Added) => "added by calendar_dates".to_owned(),
+        ServiceDay::Exception(Exception::Deleted) => "removed by calendar_dates".to_owned(),
+        ServiceDay::NoCalendar => "no calendar entry".to_owned(),
+        ServiceDay::OutsideCalendar(calendar) => {
+            format!("outside the calendar's {}", range(calendar))
+        }
+        ServiceDay::Weekday(calendar, true) => format!(
+            "calendar runs on {}s, {}",
+            date.format("%A"),
+            range(calendar)
+        ),
+        ServiceDay::Weekday(_, false) => {
+            format!("calendar does not run on {}s", date.format("%A"))
+        }
+    };
+    (day.runs(), reason)
+}
+
+/// The services running on `date`, as [`service_day`] decides.
+fn service_ids_for(gtfs: &Gtfs, date: NaiveDate) -> Vec<String> {
+    gtfs.calendar
+        .keys()
+        .chain(gtfs.calendar_dates.keys())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .filter(|service_id| service_day(gtfs, service_id, date).runs())
+        .cloned()
+        .collect()
+}
+
+/// The timezone in which the times of `gtfs` are given: its agencies', which GTFS requires to
+/// be the same, or Eastern when it has none or an unknown one.
+fn feed_timezone(gtfs: &Gtfs) -> Tz {
+    gtfs.agencies
+        .first()
+        .and_then(|agency| agency.timezone.parse().ok())
+        .unwrap_or(Eastern)
+}
+
+/// Instant of a static GTFS time, given in seconds after midnight of `service_date`.
+///
+/// As the GTFS reference specifies, times count from noon minus 12 hours rather than from
+/// midnight, so they stay evenly spaced on the days clocks change: a 06:00:00 departure is
+/// six hours after the service day starts even when midnight and 06:00 have different UTC
+/// offsets. Times past 24:00:00 land on the following calendar day. `None` when the result
+/// is out of the representable range. Times are read in `tz`, the feed's timezone.
+fn scheduled_time(service_date: NaiveDate, seconds: u32, tz: Tz) -> Option<DateTime<Tz>> {
+    // Clocks only ever change at night, so noon is never skipped nor repeated.
+    let noon = tz
+        .from_local_datetime(&service_date.and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap()))
+        .single()
+        .expect("Noon is unambiguous");
+    (noon - TimeDelta::hours(12)).checked_add_signed(TimeDelta::seconds(seconds.into()))
+}
+
+/// The expected time of an event `scheduled` at the given time, once the realtime `delay`
+/// in seconds, if any, is applied; `None` when the result is out of range.
+///
+/// Departures only ever hold the result, never the scheduled time, so that a delay cannot be
+/// applied twice.
+fn delayed(scheduled: DateTime<Tz>, delay: Option<i32>) -> Option<DateTime<Tz>> {
+    scheduled.checked_add_signed(TimeDelta::seconds(delay.unwrap_or(0).into()))
+}
+
+/// The instant the clocks of `tz` read `time` on `date`, or right after clocks spring forward
+/// past it.
+fn local_instant(date: NaiveDate, time: NaiveTime, tz: Tz) -> DateTime<Tz> {
+    let mut local = date.and_time(time);
+    loop {
+        if let Some(instant) = tz.from_local_datetime(&local).earliest() {
+            return instant;
+        }
+        local += TimeDelta::minutes(1);
+    }
+}
+
+/// Whether a departure at `time` should be shown at `now`.
+///
+/// The window starts `include_past` before `now`, inclusive, so with no lookback a
+/// departure scheduled at exactly midnight is still listed at 00:00:00. It ends `window`
+/// after `now` when one is given, and otherwise at the next `DAY_TRANSITION`: in the morning,
+/// wait until DAY_TRANSITION to show the trains for the day. The end is inclusive too, and a
+/// new day's window opens at DAY_TRANSITION itself, so a departure at exactly DAY_TRANSITION
+/// is listed both before and after it, never dropping out.
+fn in_window(
+    time: DateTime<Tz>,
+    now: DateTime<Tz>,
+    include_past: TimeDelta,
+    window: Option<TimeDelta>,
+) -> bool {
+    let end = if let Some(window) = window {
+        now + window
+    } else if now.time() >= DAY_TRANSITION {
+        local_instant(
+            now.date_naive()
+                .checked_add_days(Days::new(1))
+                .expect("After common era!"),
+            DAY_TRANSITION,
+            now.timezone(),
+        )
+    } else {
+        local_instant(now.date_naive(), DAY_TRANSITION, now.timezone())
+    };
+    now - include_past <= time && time <= end
+}
+
+/// When the service day running at `now` began, at `DAY_TRANSITION`, matching the end of
+/// the window of [`in_window`].
+fn service_day_start(now: DateTime<Tz>) -> DateTime<Tz> {
+    let date = if now.time() >= DAY_TRANSITION {
+        now.date_naive()
+    } else {
+        now.date_naive()
+            .checked_sub_days(Days::new(1))
+            .expect("Before common era!")
+    };
+    local_instant(date, DAY_TRANSITION, now.timezone())
+}
+
+/// Prints the last train among `departures` that is not cancelled, with how long from `now`
+/// it leaves or left.
+fn print_last(departures: &[Departure], now: DateTime<Tz>, args: &Cli) -> anyhow::Result<()> {
+    let Some(last) = departures
+        .iter()
+        .filter(|departure| !departure.cancelled)
+        .max_by_key(|departure| departure.time)
+    else {
+        bail!("No train leaves the station this service day");
+    };
+    let lang = args.lang.unwrap_or_else(Lang::from_env);
+    let minutes = (last.time - now).num_minutes();
+    let relative = if minutes < 0 {
+        lang.fill("left {} ago", &[&duration_label(-minutes)])
+    } else {
+        lang.fill("in {}", &[&duration_label(minutes)])
+    };
+    println!(
+        "{}",
+        lang.fill(
+            "Last train: {} to {}, {}",
+            &[&last.time.format(&args.timefmt), &last.headsign, &relative]
+        )
+    );
+    Ok(())
+}
+
+/// Like `25 min` or `5 h 2 min`.
+fn duration_label(minutes: i64) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{minutes} min"),
+        (hours, minutes) => format!("{hours} h {minutes} min"),
+    }
+}
+
+/// Prints the first scheduled boarding departure at the station's `stop_ids` during the
+/// service day after the one running at `now`, with how long from `now` it leaves.
+///
+/// That day runs from DAY_TRANSITION on its date to DAY_TRANSITION the next morning, so its
+/// trains come from its own service and from the trips of the day before running past
+/// DAY_TRANSITION, beyond the window of [`upcoming_departures`].
+fn print_first(
+    gtfs: &Gtfs,
+    stop_ids: &[String],
+    now: DateTime<Tz>,
+    args: &Cli,
+) -> anyhow::Result<()> {
+    let date = service_day_start(now)
+        .date_naive()
+        .checked_add_days(Days::new(1))
+        .expect("After common era!");
+    let start = local_instant(date, DAY_TRANSITION, now.timezone());
+    let end = local_instant(
+        date.checked_add_days(Days::new(1))
+            .expect("After common era!"),
+        DAY_TRANSITION,
+        now.timezone(),
+    );
+    let service_dates = [
+        date.checked_sub_days(Days::new(1))
+            .expect("Before common era!"),
+        date,
+    ];
+    let first = service_dates
+        .into_iter()
+        .flat_map(|service_date| {
+            let service_ids = service_ids_for(gtfs, service_date);
+            gtfs.trips
+                .values()
+                .filter(move |trip| service_ids.contains(&trip.service_id))
+                .flat_map(move |trip| {
+                    trip.stop_times
+                        .iter()
+                        .enumerate()
+                        .filter(|(_index, stop_time)| {
+                            stop_ids.contains(&stop_time.stop.id)
+                                && Board::Departures.lists(stop_time)
+                        })
+                        .filter_map(move |(index, _stop_time)| {
+                            let (seconds, _approximate) =
+                                Board::Departures.scheduled_time(trip, index)?;
+                            Some((scheduled_time(service_date, seconds, now.timezone())?, trip))
+                        })
+                })
+        })
+        .filter(|(time, _trip)| start <= *time && *time < end)
+        .min_by_key(|(time, _trip)| *time);
+    let Some((time, trip)) = first else {
+        bail!("No train leaves the station on the next service day ({date})");
+    };
+    let lang = args.lang.unwrap_or_else(Lang::from_env);
+    println!(
+        "{}",
+        lang.fill(
+            "First train: {} to {}, in {}",
+            &[
+                &time.format(&args.timefmt),
+                &trip
+                    .trip_headsign
+                    .as_deref()
+                    .unwrap_or(lang.tr("(no headsign)")),
+                &duration_label((time - now).num_minutes()),
+            ]
+        )
+    );
+    Ok(())
+}
+
+/// Scheduled departure at `trip.stop_times[index]`, in seconds after midnight, and whether
+/// that time is only approximate.
+///
+/// Stops without a time of their own are interpolated between the closest surrounding stops
+/// whose times are exact timepoints, by distance travelled when the feed provides it and by
+/// stop count otherwise.
+fn scheduled_departure(trip: &Trip, index: usize) -> Option<(u32, bool)> {
+    let stop_time = &trip.stop_times[index];
+    if let Some(seconds) = stop_time.departure_time.or(stop_time.arrival_time) {
+        return Some((seconds, stop_time.timepoint == TimepointType::Approximate));
+    }
+
+    let is_exact = |other: &StopTime| {
+        other.timepoint == TimepointType::Exact
+            && (other.departure_time.is_some() || other.arrival_time.is_some())
+    };
+    let before_index = trip.stop_times[..index].iter().rposition(is_exact)?;
+    let after_index = index + 1 + trip.stop_times[index + 1..].iter().position(is_exact)?;
+    let (before, after) = (
+        &trip.stop_times[before_index],
+        &trip.stop_times[after_index],
+    );
+    let start = before.departure_time.or(before.arrival_time)?;
+    let end = after.arrival_time.or(after.departure_time)?;
+
+    let fraction = match (
+        before.shape_dist_traveled,
+        stop_time.shape_dist_traveled,
+        after.shape_dist_traveled,
+    ) {
+        (Some(from), Some(here), Some(to)) if to > from => f64::from((here - from) / (to - from)),
+        _ => (index - before_index) as f64 / (after_index - before_index) as f64,
+    };
+    let seconds = f64::from(start) + f64::from(end.saturating_sub(start)) * fraction;
+    Some((seconds.round() as u32, true))
+}
+
+/// The trips of `gtfs` that have a `block_id`, by block, for [`next_in_block`].
+fn trips_by_block(gtfs: &Gtfs) -> HashMap<&str, Vec<&Trip>> {
+    let mut blocks = HashMap::<_, Vec<_>>::new();
+    for trip in gtfs.trips.values() {
+        if let Some(block_id) = &trip.block_id {
+            blocks.entry(block_id.as_str()).or_default().push(trip);
+        }
+    }
+    blocks
+}
+
+/// The trip run by the same vehicle right after `trip`, found through its `block_id` among
+/// `blocks`, see [`trips_by_block`].
+fn next_in_block<'a>(blocks: &HashMap<&str, Vec<&'a Trip>>, trip: &Trip) -> Option<&'a Trip> {
+    let block = blocks.get(trip.block_id.as_deref()?)?;
+    let end = trip.stop_times.last()?;
+    let end = end.arrival_time.or(end.departure_time)?;
+    block
+        .iter()
+        .copied()
+        .filter(|other| other.id != trip.id && other.service_id == trip.service_id)
+        .filter_map(|other| {
+            let start = other.stop_times.first()?;
+            let start = start.departure_time.or(start.arrival_time)?;
+            (start >= end).then_some((start, other))
+        })
+        .min_by_key(|(start, _other)| *start)
+        .map(|(_start, other)| other)
+}
+
+/// The realtime update for `trip`, if the feed has one, with the id of its entity.
+///
+/// An update naming a different route than the static trip most likely refers to a trip_id
+/// reused across schedule versions, so it is ignored with a warning rather than applied.
+fn trip_update<'a>(
+    realtime_data: &'a FeedMessage,
+    trip: &Trip,
+) -> Option<(&'a str, &'a TripUpdate)> {
+    let (entity_id, update) = realtime_data.entity.iter().find_map(|entity| {
+        let update = entity.trip_update.as_ref()?;
+        (update.trip.trip_id.as_ref() == Some(&trip.id)).then_some((entity.id.as_str(), update))
+    })?;
+    if let Some(route_id) = &update.trip.route_id
+        && *route_id != trip.route_id
+    {
+        eprintln!(
+            "WARNING: ignoring realtime update for trip {} on route {route_id}, \
+             which the static feed puts on route {}",
+            trip.id, trip.route_id
+        );
+        return None;
+    }
+    Some((entity_id, update))
+}
+
+/// A realtime delay read from a trip update.
+#[derive(Clone)]
+struct Prediction {
+    /// Delay in seconds
+    delay: i32,
+    /// Uncertainty of the delay in seconds, when the feed gives one
+    uncertainty: Option<i32>,
+    /// The stop update it was read from, like `VM #2`
+    source: String,
+}
+
+/// The delay `update` predicts when leaving each stop of `trip`, in the order of
+/// `trip.stop_times`.
+///
+/// Following the GTFS-realtime propagation rules, a stop without an update of its own keeps
+/// the delay of the closest upstream stop that has one. An update marked NO_DATA means the
+/// schedule holds from that stop on, until a later update, and a SKIPPED one only tells that
+/// the train does not stop there, so neither provides a delay.
+///
+/// Feeds sometimes predict only the arrival at stops near the terminus. Unless `strict`, the
+/// arrival delay of such a stop update is taken as its departure delay: a best-effort estimate,
+/// as the train may make up for it during its dwell, but closer than the upstream delay.
+fn delay_timeline(update: &TripUpdate, trip: &Trip, strict: bool) -> Vec<Option<Prediction>> {
+    let mut current: Option<Prediction> = None;
+    trip.stop_times
+        .iter()
+        .map(|stop_time| {
+            let Some(stop) = stop_update(update, stop_time) else {
+                return current.clone();
+            };
+            match stop.schedule_relationship() {
+                StopRelationship::Skipped => return None,
+                StopRelationship::NoData => current = None,
+                _ => {
+                    let departure = stop
+                        .departure
+                        .as_ref()
+                        .filter(|event| event.delay.is_some());
+                    let arrival = stop.arrival.as_ref().filter(|event| event.delay.is_some());
+                    let (event, from_arrival) = match (departure, arrival) {
+                        (Some(event), _) => (Some(event), false),
+                        (None, Some(event)) if !strict => (Some(event), true),
+                        _ => (None, false),
+                    };
+                    if let Some(event) = event {
+                        current = event.delay.map(|delay| Prediction {
+                            delay,
+                            uncertainty: event.uncertainty,
+                            source: format!(
+                                "{} #{}{}",
+                                stop.stop_id.as_deref().unwrap_or(&stop_time.stop.id),
+                                stop.stop_sequence.unwrap_or(stop_time.stop_sequence),
+                                if from_arrival { " arrival" } else { "" }
+                            ),
+                        });
+                    }
+                }
+            }
+            current.clone()
+        })
+        .collect()
+}
+
+/// Delay predicted by `update` for `trip` when leaving `trip.stop_times[index]`, as given by
+/// [`delay_timeline`].
+fn realtime_delay(
+    update: &TripUpdate,
+    trip: &Trip,
+    index: usize,
+    strict: bool,
+) -> Option<Prediction> {
+    delay_timeline(update, trip, strict)
+        .into_iter()
+        .nth(index)
+        .flatten()
+}
+
+/// The part of `update` about `stop_time`, matched by stop sequence when the update gives
+/// one and by stop id otherwise.
+fn stop_update<'a>(update: &'a TripUpdate, stop_time: &StopTime) -> Option<&'a StopTimeUpdate> {
+    update
+        .stop_time_update
+        .iter()
+        .find(|stop| match stop.stop_sequence {
+            Some(sequence) => sequence == stop_time.stop_sequence,
+            None => stop.stop_id.as_ref() == Some(&stop_time.stop.id),
+        })
+}
+
+/// Whether `update` announces that the train will not call at `stop_time`.
+fn skips_stop(update: &TripUpdate, stop_time: &StopTime) -> bool {
+    stop_update(update, stop_time)
+        .is_some_and(|stop| stop.schedule_relationship() == StopRelationship::Skipped)
+}
+
+/// The platform `update` moves the train to at `stop_time`, when it assigns it another stop
+/// whose platform differs from the scheduled one.
+fn platform_change(gtfs: &Gtfs, update: &TripUpdate, stop_time: &StopTime) -> Option<String> {
+    let assigned = stop_update(update, stop_time)?
+        .stop_time_properties
+        .as_ref()?
+        .assigned_stop_id
+        .as_ref()?;
+    let platform = gtfs.stops.get(assigned)?.platform_code.clone()?;
+    (stop_time.stop.platform_code.as_ref() != Some(&platform)).then_some(platform)
+}
+
+/// Departures synthesized from realtime trips marked as added to the schedule.
+/// Their times are shown in `tz`.
+fn added_departures(realtime_data: &FeedMessage, stop_ids: &[String], tz: Tz) -> Vec<Departure> {
+    realtime_data
+        .entity
+        .iter()
+        .filter_map(|entity| {
+            let update = entity.trip_update.as_ref()?;
+            if !matches!(
+                update.trip.schedule_relationship(),
+                ScheduleRelationship::Added | ScheduleRelationship::New
+            ) {
+                return None;
+            }
+            let (stop_id, event) = update.stop_time_update.iter().find_map(|stop| {
+                let stop_id = stop.stop_id.as_ref()?;
+                if !stop_ids.contains(stop_id) {
+                    return None;
+                }
+                Some((stop_id, stop.departure.as_ref().or(stop.arrival.as_ref())?))
+            })?;
+            let timestamp = event.time?;
+            let time = DateTime::from_timestamp(timestamp, 0)?.with_timezone(&tz);
+            let headsign = update
+                .trip_properties
+                .as_ref()
+                .and_then(|properties| properties.trip_headsign.clone())
+                .unwrap_or_else(|| "Extra train".to_owned());
+            Some(Departure {
+                train_number: update
+                    .trip_properties
+                    .as_ref()
+                    .and_then(|properties| properties.trip_short_name.clone()),
+                delay: event.delay,
+                uncertainty: event.uncertainty,
+                extra: true,
+                vehicle: vehicle_name(update),
+                realtime_entity: Some(entity.id.clone()),
+                stop_id: Some(stop_id.clone()),
+                service_date: update
+                    .trip
+                    .start_date
+                    .as_deref()
+                    .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok()),
+                ..Departure::new(
+                    update
+                        .trip
+                        .trip_id
+                        .clone()
+                        .unwrap_or_else(|| entity.id.clone()),
+                    update.trip.route_id.clone().unwrap_or_default(),
+                    time,
+                    headsign,
+                )
+            })
+        })
+        .collect()
+}
+
+/// Sorts `departures` in the requested order, breaking ties by time, then route, headsign,
+/// trip id and stop, so the output doesn't depend on `HashMap` iteration order and stays the
+/// same from one run to the next.
+///
+/// By route, routes come in the feed's `route_sort_order`, routes without one last, and by
+/// route id otherwise.
+///
+/// Times are compared with their realtime delay included, so a badly delayed train sinks below
+/// the on-time trains scheduled after it.
+fn sort_departures(gtfs: &Gtfs, departures: &mut [Departure], order: SortOrder) {
+    let chronological = |a: &Departure, b: &Departure| {
+        a.time
+            .cmp(&b.time)
+            .then_with(|| a.route_id.cmp(&b.route_id))
+            .then_with(|| a.headsign.cmp(&b.headsign))
+            .then_with(|| a.trip_id.cmp(&b.trip_id))
+            .then_with(|| a.stop_id.cmp(&b.stop_id))
+    };
+    match order {
+        SortOrder::Time => departures.sort_by(chronological),
+        SortOrder::Route => {
+            let route_key = |departure: &Departure| {
+                let order = gtfs
+                    .routes
+                    .get(&departure.route_id)
+                    .and_then(|route| route.order);
+                (order.is_none(), order)
+            };
+            departures.sort_by(|a, b| {
+                route_key(a)
+                    .cmp(&route_key(b))
+                    .then_with(|| a.route_id.cmp(&b.route_id))
+                    .then_with(|| chronological(a, b))
+            });
+        }
+        SortOrder::Delay => departures.sort_by(|a, b| {
+            b.delay
+                .unwrap_or(0)
+                .cmp(&a.delay.unwrap_or(0))
+                .then_with(|| chronological(a, b))
+        }),
+    }
+}
+
+/// Warns when most realtime trip updates refer to trips the static feed doesn't have, which
+/// happens when the two feeds come from different schedule versions, typically because a
+/// cached static feed is stale. Added trips are expected to be unknown and don't count.
+fn check_feed_sync(gtfs: &Gtfs, realtime_data: &FeedMessage) {
+    let trip_ids = realtime_data
+        .entity
+        .iter()
+        .filter_map(|entity| entity.trip_update.as_ref())
+        .filter(|update| {
+            !matches!(
+                update.trip.schedule_relationship(),
+                ScheduleRelationship::Added | ScheduleRelationship::New
+            )
+        })
+        .filter_map(|update| update.trip.trip_id.as_ref())
+        .collect::<Vec<_>>();
+    let unknown = trip_ids
+        .iter()
+        .filter(|trip_id| !gtfs.trips.contains_key(**trip_id))
+        .count();
+    if unknown > 0 && unknown * 2 > trip_ids.len() {
+        eprintln!(
+            "WARNING: {unknown} of {} realtime trip updates refer to trips missing from the \
+             static feed; the feeds may be out of sync (is the static feed stale?)",
+            trip_ids.len()
+        );
+    }
+}
+
+/// Warns when the static feed's `feed_info` end date has passed, and fails once it is more
+/// than `max_age_days` in the past.
+fn check_feed_expiry(
+    gtfs: &Gtfs,
+    today: NaiveDate,
+    max_age_days: Option<i64>,
+) -> anyhow::Result<()> {
+    let Some(info) = gtfs
+        .feed_info
+        .iter()
+        .filter(|info| info.end_date.is_some())
+        .max_by_key(|info| info.end_date)
+    else {
+        return Ok(());
+    };
+    let end_date = info.end_date.expect("filtered on end_date");
+    if end_date >= today {
+        return Ok(());
+    }
+    let age = (today - end_date).num_days();
+    let version = info
+        .version
+        .as_ref()
+        .map(|version| format!(" version {version}"))
+        .unwrap_or_default();
+    if max_age_days.is_some_and(|max_age| age > max_age) {
+        bail!(
+            "Static feed{version} expired on {end_date}, {age} days ago; refusing to show departures"
+        );
+    }
+    eprintln!(
+        "WARNING: static feed{version} expired on {end_date}, {age} days ago; times may be wrong"
+    );
+    Ok(())
+}
+
+/// When `departure`, leaving from one of `from`, later lets passengers off at one of `to`:
+/// its arrival time there, assuming it keeps the delay it has at departure, and the number of
+/// stops it makes in between, not counting those it only passes through.
+fn arrival_at(
+    gtfs: &Gtfs,
+    departure: &Departure,
+    from: &[String],
+    to: &[String],
+) -> Option<(DateTime<Tz>, usize)> {
+    let trip = gtfs.trips.get(&departure.trip_id)?;
+    let origin = trip
+        .stop_times
+        .iter()
+        .position(|stop_time| from.contains(&stop_time.stop.id))?;
+    let (departure_seconds, _approximate) = scheduled_departure(trip, origin)?;
+    let (offset, arrival_seconds) = trip.stop_times[origin + 1..]
+        .iter()
+        .enumerate()
+        .filter(|(_, stop_time)| {
+            to.contains(&stop_time.stop.id)
+                && stop_time.drop_off_type != PickupDropOffType::NotAvailable
+        })
+        .find_map(|(offset, stop_time)| {
+            Some((offset, stop_time.arrival_time.or(stop_time.departure_time)?))
+        })?;
+    let intermediate_stops = trip.stop_times[origin + 1..origin + 1 + offset]
+        .iter()
+        .filter(|stop_time| {
+            stop_time.pickup_type != PickupDropOffType::NotAvailable
+                || stop_time.drop_off_type != PickupDropOffType::NotAvailable
+        })
+        .count();
+    let arrival = departure.time.checked_add_signed(TimeDelta::seconds(
+        i64::from(arrival_seconds) - i64::from(departure_seconds),
+    ))?;
+    Some((arrival, intermediate_stops))
+}
+
+/// With `--to`, keeps only the `departures` from one of `from` that then let passengers off at
+/// one of `to`, noting their arrival there and the stops in between.
+fn keep_serving(gtfs: &Gtfs, departures: &mut Vec<Departure>, from: &[String], to: &[String]) {
+    departures.retain_mut(|departure| {
+        let Some((arrival, stops)) = arrival_at(gtfs, departure, from, to) else {
+            return false;
+        };
+        departure.arrival = Some(arrival);
+        departure.intermediate_stops = Some(stops);
+        true
+    });
+}
+
+/// The first stop of the trip of `departure` among `stop_ids`, i.e. which platform of a
+/// station it serves.
+fn stop_at<'a>(gtfs: &'a Gtfs, departure: &Departure, stop_ids: &[String]) -> Option<&'a str> {
+    gtfs.trips
+        .get(&departure.trip_id)?
+        .stop_times
+        .iter()
+        .find(|stop_time| stop_ids.contains(&stop_time.stop.id))
+        .map(|stop_time| stop_time.stop.id.as_str())
+}
+
+/// Earliest departure allowed after arriving, given the `transfers.txt` rule between the
+/// arrival and departure stops, if any, with an explanation of the rule to show; `None`
+/// when the feed forbids that transfer. Without a rule, `min_connection` applies.
+fn connection_rule(
+    gtfs: &Gtfs,
+    from_stop: Option<&str>,
+    to_stop: Option<&str>,
+    arrival: DateTime<Tz>,
+    min_connection: TimeDelta,
+    lang: Lang,
+) -> Option<(DateTime<Tz>, Option<String>)> {
+    let transfer = from_stop
+        .and_then(|from_stop| gtfs.stops.get(from_stop))
+        .and_then(|stop| {
+            stop.transfers
+                .iter()
+                .find(|transfer| Some(transfer.to_stop_id.as_str()) == to_stop)
+        });
+    let Some(transfer) = transfer else {
+        return Some((arrival + min_connection, None));
+    };
+    match (transfer.transfer_type, transfer.min_transfer_time) {
+        (TransferType::Impossible, _) => None,
+        (TransferType::Timed, _) => Some((arrival, Some(lang.tr("timed transfer").to_owned()))),
+        (TransferType::StayOnBoard, _) => {
+            Some((arrival, Some(lang.tr("stay on board").to_owned())))
+        }
+        (_, Some(seconds)) => Some((
+            arrival + TimeDelta::seconds(seconds.into()),
+            Some(lang.fill("at least {} min per the agency", &[&seconds.div_ceil(60)])),
+        )),
+        _ => Some((arrival + min_connection, None)),
+    }
+}
+
+/// Prints each train from the station to `connection`, then the first train from there to
+/// `destination` that it connects to, following the feed's `transfers.txt` when it has a
+/// rule for those stops, or leaving at least `min_connection` after it arrives otherwise.
+fn print_itineraries(
+    gtfs: &Gtfs,
+    realtime_data: &FeedMessage,
+    first_legs: &[Departure],
+    (connection_name, connection): (&str, &[String]),
+    (destination_name, destination): (&str, &[String]),
+    now: DateTime<Tz>,
+    args: &Cli,
+) {
+    let lang = args.lang.unwrap_or_else(Lang::from_env);
+    let min_connection = TimeDelta::minutes(args.min_connection);
+    let mut second_legs = upcoming_departures(
+        gtfs,
+        realtime_data,
+        connection,
+        now,
+        // A connection may leave after the end of the `--window`.
+        (TimeDelta::zero(), None),
+        Board::Departures,
+        args,
+    )
+    .into_iter()
+    .filter(|departure| !departure.cancelled)
+    .filter_map(|mut departure| {
+        let (arrival, stops) = arrival_at(gtfs, &departure, connection, destination)?;
+        departure.arrival = Some(arrival);
+        departure.intermediate_stops = Some(stops);
+        Some(departure)
+    })
+    .collect::<Vec<_>>();
+    sort_departures(gtfs, &mut second_legs, SortOrder::Time);
+    for first in first_legs.iter().filter(|departure| !departure.cancelled) {
+        let Some(arrival) = first.arrival else {
+            continue;
+        };
+        let mut line = format!(
+            "{} → {} {connection_name}",
+            first.time.format(&args.timefmt),
+            arrival.format(&args.timefmt)
+        );
+        let arrival_stop = stop_at(gtfs, first, connection);
+        let second = second_legs.iter().find_map(|second| {
+            let departure_stop = stop_at(gtfs, second, connection);
+            let (earliest, rule) = connection_rule(
+                gtfs,
+                arrival_stop,
+                departure_stop,
+                arrival,
+                min_connection,
+                lang,
+            )?;
+            (second.time >= earliest).then(|| Some((second, second.arrival?, rule)))?
+        });
+        match second {
+            Some((second, second_arrival, rule)) => {
+                let rule = rule.map(|rule| format!(", {rule}")).unwrap_or_default();
+                line.push_str(&lang.fill(
+                    ", change ({} min{}), {} → {} {}",
+                    &[
+                        &(second.time - arrival).num_minutes(),
+                        &rule,
+                        &second.time.format(&args.timefmt),
+                        &second_arrival.format(&args.timefmt),
+                        &destination_name,
+                    ],
+                ));
+            }
+            None => line.push_str(lang.tr(", no connection")),
+        }
+        println!("{line}");
+    }
+}
+
+/// The destination of `trip` as the static feed gives it: its headsign or, when it has none,
+/// the name of its last stop. Empty when the feed names neither.
+fn scheduled_headsign(trip: &Trip) -> String {
+    trip.trip_headsign
+        .clone()
+        .filter(|headsign| !headsign.is_empty())
+        .or_else(|| trip.stop_times.last()?.stop.name.clone())
+        .unwrap_or_default()
+}
+
+/// The trainset running the trip of `update`, by its public label when it has one.
+fn vehicle_name(update: &TripUpdate) -> Option<String> {
+    let vehicle = update.vehicle.as_ref()?;
+    vehicle.label.clone().or_else(|| vehicle.id.clone())
+}
+
+/// Departures, or arrivals depending on `board`, at the station's `stop_ids` within the
+/// display window around `now`, with realtime delays applied and extra trains added, in no
+/// particular order. `args` selects how delays are read and whether dates are deduplicated.
+pub fn upcoming_departures(
+    gtfs: &Gtfs,
+    realtime_data: &FeedMessage,
+    stop_ids: &[String],
+    now: DateTime<Tz>,
+    (include_past, window): (TimeDelta, Option<TimeDelta>),
+    board: Board,
+    args: &Cli,
+) -> Vec<Departure> {
+    let strict_delays = args.strict_delays;
+    let today = now.date_naive();
+    let yesterday = today
+        .checked_sub_days(Days::new(1))
+        .expect("Before common era!");
+    let tomorrow = today
+        .checked_add_days(Days::new(1))
+        .expect("After common era!");
+
+    // Only gathered once a departure needs it.
+    let blocks = OnceCell::new();
+    let blocks = &blocks;
+    gtfs.trips
+        .iter()
+        .flat_map(|(trip_id, trip)| {
+            // Looked up at the trip's first call here, and only then, so that a mismatched
+            // update is warned about once.
+            let matched = OnceCell::new();
+            trip.stop_times
+                .iter()
+                .enumerate()
+                // stops at this station for boarding, or alighting
+                .filter(|(_index, stop_time)| {
+                    stop_ids.contains(&stop_time.stop.id) && board.lists(stop_time)
+                })
+                // Select relevant time ranges
+                .map(move |(index, stop_time)| {
+                    let Some((departure_seconds, approximate)) = board.scheduled_time(trip, index)
+                    else {
+                        return Vec::new();
+                    };
+                    let matched = *matched.get_or_init(|| trip_update(realtime_data, trip));
+                    let update = matched.map(|(_entity_id, update)| update);
+                    if update.is_some_and(|update| skips_stop(update, stop_time)) {
+                        return Vec::new();
+                    }
+                    let prediction = update
+                        .and_then(|update| realtime_delay(update, trip, index, strict_delays));
+                    let delay = prediction.as_ref().map(|prediction| prediction.delay);
+                    let cancelled = update.is_some_and(|update| {
+                        matches!(
+                            update.trip.schedule_relationship(),
+                            ScheduleRelationship::Canceled | ScheduleRelationship::Deleted
+                        )
+                    });
+                    let vehicle = update.and_then(vehicle_name);
+                    // Diverted trains may get a new destination in the realtime feed.
+                    let headsign = update
+                        .and_then(|update| update.trip_properties.as_ref()?.trip_headsign.clone())
+                        .filter(|headsign| !headsign.is_empty())
+                        .unwrap_or_else(|| scheduled_headsign(trip));
+                    let train_number = update
+                        .and_then(|update| {
+                            update.trip_properties.as_ref()?.trip_short_name.clone()
+                        })
+                        .or_else(|| trip.trip_short_name.clone());
+                    let platform_change =
+                        update.and_then(|update| platform_change(gtfs, update, stop_time));
+                    let continues_as = trip
+                        .block_id
+                        .as_ref()
+                        .and_then(|_| {
+                            next_in_block(blocks.get_or_init(|| trips_by_block(gtfs)), trip)
+                        })
+                        .and_then(|next| next.trip_headsign.clone());
+                    [yesterday, today, tomorrow]
+                        .iter()
+                        .filter(|&date| service_ids_for(gtfs, *date).contains(&trip.service_id))
+                        .filter_map(|date| {
+                            let Some(scheduled) = scheduled_time(*date, departure_seconds, now.timezone())
+                            else {
+                                eprintln!(
+                                    "WARNING: Skipping trip {trip_id}, whose scheduled time is out of range"
+                                );
+                                return None;
+                            };
+                            // Yesterday's service only matters for its trips that are part of
+                            // the current service day: those past midnight, or all of them
+                            // before DAY_TRANSITION. Its other trips are today's, a day early.
+                            if *date == yesterday
+                                && scheduled < service_day_start(now)
+                                && !args.no_dedup_dates
+                            {
+                                return None;
+                            }
+                            let Some(time) = delayed(scheduled, delay) else {
+                                eprintln!(
+                                    "WARNING: Skipping trip {trip_id}, whose delay of {} s is out of range",
+                                    delay.unwrap_or_default()
+                                );
+                                return None;
+                            };
+                            Some(Departure {
+                                trip_id: trip_id.clone(),
+                                route_id: trip.route_id.clone(),
+                                route_name: None,
+                                time,
+                                delay,
+                                headsign: headsign.clone(),
+                                train_number: train_number.clone(),
+                                extra: false,
+                                approximate,
+                                block_id: trip.block_id.clone(),
+                                continues_as: continues_as.clone(),
+                                cancelled,
+                                platform: stop_time.stop.platform_code.clone(),
+                                platform_change: platform_change.clone(),
+                                restriction: board.restriction(stop_time),
+                                vehicle: vehicle.clone(),
+                                arrival: None,
+                                intermediate_stops: None,
+                                highlighted: false,
+                                reachable: None,
+                                first_reachable: false,
+                                uncertainty: prediction
+                                    .as_ref()
+                                    .and_then(|prediction| prediction.uncertainty),
+                                delay_source: prediction
+                                    .as_ref()
+                                    .map(|prediction| prediction.source.clone()),
+                                realtime_entity: matched
+                                    .map(|(entity_id, _update)| entity_id.to_owned()),
+                                stop_id: Some(stop_time.stop.id.clone()),
+                                service_date: Some(*date),
+                                trend: None,
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+        })
+        .flatten()
+        .chain(added_departures(realtime_data, stop_ids, now.timezone()))
+        .filter(|departure| in_window(departure.time, now, include_past, window))
+        .collect()
+}
+
+/// Resolves where the static and realtime feeds are read from, with `token` for the
+/// `<token>` placeholder of realtime URLs.
+fn feed_sources(args: &Cli, token: Option<&str>) -> anyhow::Result<(FeedSource, Vec<FeedSource>)> {
+    let static_source = match (&args.static_path, args.static_max_age) {
+        (Some(path), _) => FeedSource::Path(path.clone()),
+        (None, Some(hours)) => FeedSource::Cache {
+            url: args.agency.static_url.to_owned(),
+            path: feed::cache_dir()
+                .context("No cache directory; set XDG_CACHE_HOME or HOME")?
+                .join("google_transit.zip"),
+            max_age: Duration::from_secs(hours * 3600),
+        },
+        (None, None) => FeedSource::Url(args.agency.static_url.to_owned()),
+    };
+    if let Some(path) = &args.realtime_path {
+        return Ok((static_source, vec![FeedSource::Path(path.clone())]));
+    }
+    let with_token = |url: String| match token {
+        Some(token) => url.replace("<token>", token),
+        None => url,
+    };
+    if let Some(url) = &args.realtime_stream {
+        let stream = FeedSource::Stream {
+            url: with_token(url.clone()),
+            stream: Default::default(),
+        };
+        return Ok((static_source, vec![stream]));
+    }
+    let urls = if args.realtime_urls.is_empty() {
+        vec![args.agency.realtime_url.to_owned()]
+    } else {
+        args.realtime_urls.clone()
+    };
+    let realtime_sources = urls
+        .into_iter()
+        .map(|url| FeedSource::Url(with_token(url)))
+        .collect();
+    Ok((static_source, realtime_sources))
+}
+
+/// The client fetching both feeds, through `--proxy` when given. Otherwise reqwest follows the
+/// standard proxy environment variables.
+fn http_client(args: &Cli) -> anyhow::Result<Client> {
+    let mut builder = Client::builder();
+    if let Some(proxy) = &args.proxy {
+        let proxy =
+            reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy URL `{proxy}`"))?;
+        builder = builder.proxy(proxy);
+    }
+    builder.build().context("Could not set up the HTTP client")
+}
+
+/// The largest realtime feed accepted, in bytes, from `--max-realtime-size`.
+fn realtime_size_limit(args: &Cli) -> usize {
+    args.max_realtime_size.saturating_mul(1024 * 1024)
+}
+
+/// What a run with `args` would fetch and show, with the API token masked. A token file
+/// that cannot be read is reported rather than failing the dry run.
+fn dry_run(args: &Cli) -> anyhow::Result<String> {
+    use std::fmt::Write as _;
+
+    let token = args.token_file.as_deref().map(read_token_file).transpose();
+    let readable_token = token.as_ref().ok().and_then(Option::as_deref);
+    let (static_source, realtime_sources) = feed_sources(args, readable_token)?;
+    let masked = |description: String| match readable_token {
+        Some(token) => description.replace(token, "***"),
+        None => description,
+    };
+    let mut out = String::new();
+    writeln!(out, "agency: {}", args.agency.name).unwrap();
+    writeln!(out, "static feed: {}", static_source.describe()).unwrap();
+    for source in &realtime_sources {
+        writeln!(out, "realtime feed: {}", masked(source.describe())).unwrap();
+    }
+    if let Err(err) = &token {
+        writeln!(out, "token: unreadable, {err:#}").unwrap();
+    } else if realtime_sources
+        .iter()
+        .any(|source| matches!(source, FeedSource::Url(url) if url.contains("<token>")))
+    {
+        writeln!(
+            out,
+            "token: missing, the realtime URL still has a <token> placeholder"
+        )
+        .unwrap();
+    } else if readable_token.is_some() {
+        writeln!(out, "token: read from the token file").unwrap();
+    }
+    if let Some(station) = &args.station {
+        writeln!(out, "station: {station}").unwrap();
+    }
+    for station in &args.also {
+        writeln!(out, "also: {station}").unwrap();
+    }
+    if let Some(path) = &args.aliases {
+        writeln!(out, "aliases: {}", path.display()).unwrap();
+    }
+    for station in &args.to {
+        writeln!(out, "to: {station}").unwrap();
+    }
+    if args.arrivals {
+        writeln!(out, "board: arrivals").unwrap();
+    } else if args.include_no_pickup {
+        writeln!(out, "board: all calls").unwrap();
+    }
+    for route in &args.routes {
+        writeln!(out, "route: {route}").unwrap();
+    }
+    for trip_id in &args.include_trips {
+        writeln!(out, "include trip: {trip_id}").unwrap();
+    }
+    for trip_id in &args.exclude_trips {
+        writeln!(out, "exclude trip: {trip_id}").unwrap();
+    }
+    if args.only_realtime {
+        writeln!(out, "only realtime: yes").unwrap();
+    }
+    if let Some(station) = &args.highlight {
+        writeln!(out, "highlight: {station}").unwrap();
+    }
+    if let Some(minutes) = args.walk_time {
+        writeln!(
+            out,
+            "walk time: {minutes} min, boarding buffer: {} min",
+            args.boarding_buffer
+        )
+        .unwrap();
+    }
+    writeln!(out, "include past: {} min", args.include_past).unwrap();
+    if let Some(hours) = args.window {
+        writeln!(out, "window: {hours} h").unwrap();
+    }
+    if let Some(limit) = args.limit {
+        writeln!(out, "limit: {limit}").unwrap();
+    }
+    writeln!(out, "sort: {:?}", args.sort).unwrap();
+    writeln!(out, "format: {:?}", args.format).unwrap();
+    if let Some(seconds) = args.watch {
+        writeln!(out, "watch: every {seconds} s").unwrap();
+    }
+    if let Some(path) = &args.output {
+        writeln!(out, "output: {}", path.display()).unwrap();
+    }
+    Ok(out)
+}
+
+/// Fetches and decodes both feeds, printing a one-line status and exiting nonzero on failure.
+async fn healthcheck(
+    client: &Client,
+    static_source: &FeedSource,
+    realtime_sources: &[FeedSource],
+    max_size: usize,
+) -> anyhow::Result<()> {
+    let (gtfs_static, realtime_data) = join!(
+        feed::fetch_static(client, static_source),
+        feed::fetch_realtime_merged(client, realtime_sources, max_size, None)
+    );
+    let static_status = match &gtfs_static {
+        Ok(gtfs) => format!("static ok ({} trips)", gtfs.trips.len()),
+        Err(err) => format!("static failed ({err:#})"),
+    };
+    let realtime_status = match &realtime_data {
+        Ok(feed) if feed.entity.is_empty() => "realtime ok (empty)".to_owned(),
+        Ok(feed) => format!("realtime ok ({} entities)", feed.entity.len()),
+        Err(err) => format!("realtime failed ({err:#})"),
+    };
+    println!("{static_status}; {realtime_status}");
+    if gtfs_static.is_err() || realtime_data.is_err() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Prints the metadata of the static and realtime feeds and the size of the static one.
+async fn feed_info(
+    client: &Client,
+    static_source: &FeedSource,
+    realtime_sources: &[FeedSource],
+    max_size: usize,
+) -> anyhow::Result<()> {
+    let (gtfs_static, realtime_data) = join!(
+        feed::fetch_static(client, static_source),
+        feed::fetch_realtime_merged(client, realtime_sources, max_size, None)
+    );
+    let gtfs = gtfs_static.context("Could not load the static feed")?;
+    println!("Static feed");
+    if gtfs.feed_info.is_empty() {
+        println!("  No feed_info.txt");
+    }
+    for info in &gtfs.feed_info {
+        println!("  Publisher: {} ({})", info.name, info.url);
+        println!("  Language:  {}", info.lang);
+        if let Some(version) = &info.version {
+            println!("  Version:   {version}");
+        }
+        let date = |date: Option<NaiveDate>| {
+            date.map_or_else(|| "unspecified".to_owned(), |date| date.to_string())
+        };
+        println!(
+            "  Valid:     {} to {}",
+            date(info.start_date),
+            date(info.end_date)
+        );
+    }
+    println!(
+        "  {} agencies, {} routes, {} trips, {} stops",
+        gtfs.agencies.len(),
+        gtfs.routes.len(),
+        gtfs.trips.len(),
+        gtfs.stops.len()
+    );
+
+    println!("Realtime feed");
+    match realtime_data {
+        Ok(feed) => {
+            let header = &feed.header;
+            println!("  GTFS-realtime version: {}", header.gtfs_realtime_version);
+            println!(
+                "  Generated: {}",
+                header
+                    .timestamp
+                    .and_then(|timestamp| DateTime::from_timestamp(timestamp.try_into().ok()?, 0))
+                    .map_or_else(
+                        || "unknown".to_owned(),
+                        |generated| generated.with_timezone(&feed_timezone(&gtfs)).to_rfc3339()
+                    )
+            );
+            println!("  Incrementality: {:?}", header.incrementality());
+            println!("  {} entities", feed.entity.len());
+        }
+        Err(err) => println!("  Unavailable: {err:#}"),
+    }
+    Ok(())
+}
+
+/// Prints why `trip_id` is or isn't listed on `board` at the station: its service on each
+/// candidate day, its stop there, its realtime update and the window check, following the same
+/// rules as [`upcoming_departures`], with the display window of the command line.
+fn explain_trip(
+    gtfs: &Gtfs,
+    realtime_data: &FeedMessage,
+    stop_ids: &[String],
+    trip_id: &str,
+    now: DateTime<Tz>,
+    board: Board,
+    args: &Cli,
+) -> anyhow::Result<()> {
+    let include_past = TimeDelta::minutes(args.include_past);
+    let window = args.window.map(TimeDelta::hours);
+    let Some(trip) = gtfs.trips.get(trip_id) else {
+        bail!("Trip `{trip_id}` is not in the static feed");
+    };
+    println!(
+        "Trip {trip_id}: route {}, service {}, headsign {}",
+        trip.route_id,
+        trip.service_id,
+        trip.trip_headsign.as_deref().unwrap_or("(none)")
+    );
+    let today = now.date_naive();
+    let days = [
+        ("yesterday", today.checked_sub_days(Days::new(1))),
+        ("today", Some(today)),
+        ("tomorrow", today.checked_add_days(Days::new(1))),
+    ];
+    for (label, date) in days {
+        let Some(date) = date else { continue };
+        let (active, reason) = service_reason(gtfs, &trip.service_id, date);
+        let state = if active { "active" } else { "inactive" };
+        println!("  Service on {date} ({label}): {state}, {reason}");
+    }
+
+    let matched = trip_update(realtime_data, trip);
+    match matched {
+        Some((entity_id, update)) => println!(
+            "  Realtime: update found in entity {entity_id}, {:?}, {} stop time updates",
+            update.trip.schedule_relationship(),
+            update.stop_time_update.len()
+        ),
+        None => println!("  Realtime: no matching update"),
+    }
+    let update = matched.map(|(_entity_id, update)| update);
+
+    let stops = trip
+        .stop_times
+        .iter()
+        .enumerate()
+        .filter(|(_index, stop_time)| stop_ids.contains(&stop_time.stop.id))
+        .collect::<Vec<_>>();
+    if stops.is_empty() {
+        println!("  Does not stop at the station");
+    }
+    for (index, stop_time) in stops {
+        println!(
+            "  Stop {} (sequence {}), pickup {:?}, drop-off {:?}",
+            stop_time.stop.id,
+            stop_time.stop_sequence,
+            stop_time.pickup_type,
+            stop_time.drop_off_type
+        );
+        if !board.lists(stop_time) {
+            match board {
+                Board::Arrivals => println!("    Not listed: no alighting at this stop"),
+                _ => println!("    Not listed: no boarding at this stop"),
+            }
+            continue;
+        }
+        if update.is_some_and(|update| skips_stop(update, stop_time)) {
+            println!("    Not listed: the realtime update skips this stop");
+            continue;
+        }
+        let Some((seconds, approximate)) = board.scheduled_time(trip, index) else {
+            println!("    Not listed: no departure time, even interpolated");
+            continue;
+        };
+        println!(
+            "    Scheduled {:02}:{:02}:{:02}{}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            if approximate { " (approximate)" } else { "" }
+        );
+        let prediction =
+            update.and_then(|update| realtime_delay(update, trip, index, args.strict_delays));
+        if let Some(prediction) = &prediction {
+            println!(
+                "    Realtime delay {} s{}, from the update for {}",
+                prediction.delay,
+                prediction
+                    .uncertainty
+                    .map(|uncertainty| format!(" (uncertainty {uncertainty} s)"))
+                    .unwrap_or_default(),
+                prediction.source
+            );
+        }
+        let delay = prediction.map(|prediction| prediction.delay);
+        for (label, date) in days {
+            let Some(date) = date else { continue };
+            if !service_ids_for(gtfs, date).contains(&trip.service_id) {
+                continue;
+            }
+            let Some((scheduled, time)) = scheduled_time(date, seconds, now.timezone())
+                .and_then(|scheduled| Some((scheduled, delayed(scheduled, delay)?)))
+            else {
+                println!("    For {label}'s service: time out of range");
+                continue;
+            };
+            // As on the board, by the scheduled time, which delays do not move to another day.
+            let verdict = if label == "yesterday"
+                && scheduled < service_day_start(now)
+                && !args.no_dedup_dates
+            {
+                "not listed, it belongs to an earlier service day"
+            } else if in_window(time, now, include_past, window) {
+                "listed"
+            } else {
+                "not listed, outside the display window"
+            };
+            println!(
+                "    For {label}'s service: {}, {verdict}",
+                time.format("%Y-%m-%d %H:%M")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Realtime feeds captured in `dir`, by the timestamp in their header, oldest first.
+fn load_captures(dir: &Path) -> anyhow::Result<Vec<(DateTime<Utc>, PathBuf)>> {
+    let mut captures = Vec::new();
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let bytes =
+            std::fs::read(&path).with_context(|| format!("Could not read {}", path.display()))?;
+        let message: FeedMessage = prost::Message::decode(bytes.as_ref())
+            .with_context(|| format!("Could not decode {}", path.display()))?;
+        let Some(timestamp) = message
+            .header
+            .timestamp
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp.try_into().ok()?, 0))
+        else {
+            bail!("{} has no header timestamp", path.display());
+        };
+        captures.push((timestamp, path));
+    }
+    if captures.is_empty() {
+        bail!("No captured realtime feeds in {}", dir.display());
+    }
+    captures.sort();
+    Ok(captures)
+}
+
+/// Watch mode over captured realtime feeds: advances a simulated clock by `interval` on every
+/// refresh, without waiting, until the last capture has been shown.
+async fn replay(
+    args: &Cli,
+    client: &Client,
+    static_source: &FeedSource,
+    dir: &Path,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let captures = load_captures(dir)?;
+    let (last, _) = captures[captures.len() - 1];
+    let mut clock = args
+        .now
+        .map_or(captures[0].0, |now| now.with_timezone(&Utc));
+    let step = TimeDelta::from_std(interval).context("Watch interval is too long")?;
+    let mut state = WatchState::default();
+    if let Err(err) = resolve_for_watch(args, client, static_source, &mut state).await {
+        eprintln!("Error: {err:#}");
+    }
+    // One refresh past the last capture, so that it is shown too.
+    while clock < last + step {
+        eprintln!(
+            "=== as of {} ===",
+            clock.with_timezone(&Eastern).to_rfc3339()
+        );
+        let current = captures
+            .iter()
+            .rev()
+            .find(|(timestamp, _path)| *timestamp <= clock);
+        match current {
+            Some((_timestamp, path)) => {
+                let sources = [FeedSource::Path(path.clone())];
+                if let Err(err) = show_departures(
+                    args,
+                    client,
+                    static_source,
+                    &sources,
+                    &FixedClock(clock),
+                    Some(&mut state),
+                )
+                .await
+                {
+                    eprintln!("Error: {err:#}");
+                }
+            }
+            None => eprintln!("Error: No realtime feed captured yet"),
+        }
+        clock += step;
+    }
+    Ok(())
+}
+
+/// Resolves `station`, as given on the command line, to the name of a station of the feed and
+/// its stop ids, following `aliases` and the matching flags.
+fn resolve_station(
+    gtfs: &Gtfs,
+    station: &str,
+    aliases: &HashMap<String, String>,
+    args: &Cli,
+) -> anyhow::Result<(String, Vec<String>)> {
+    let alias_target = aliases.get(station);
+    let station_name = alias_target.map_or(station, String::as_str);
+    let coded = args
+        .by_code
+        .then(|| station_with_code(gtfs, station_name))
+        .flatten();
+    let by_code = coded.is_some();
+    let (mut matched_name, mut matched_by) = match coded {
+        Some(name) => (name, "stop code"),
+        None => (station_name.to_owned(), "name"),
+    };
+    let mut stop_ids = if args.station_contains && alias_target.is_none() && !by_code {
+        Vec::new()
+    } else {
+        stop_ids_named(gtfs, &matched_name)
+    };
+
+    if stop_ids.is_empty() {
+        if let Some(target) = alias_target {
+            bail!("Alias `{station}` points to `{target}`, which is not a station in the feed")
+        }
+        if args.station_exact {
+            bail!("No station is named exactly `{station}`");
+        }
+        matched_name = disambiguate_station(gtfs, station)?;
+        matched_by = "partial name";
+        stop_ids = stop_ids_named(gtfs, &matched_name);
+    }
+    if args.verbose {
+        eprintln!(
+            "`{station}` matched by {matched_by}: {matched_name} (stops {})",
+            stop_ids.join(", ")
+        );
+    }
+    Ok((matched_name, stop_ids))
+}
+
+/// Resolves the station on the command line and those of `--also`, in that order, with
+/// [`resolve_station`].
+fn resolve_stations(
+    gtfs: &Gtfs,
+    aliases: &HashMap<String, String>,
+    args: &Cli,
+) -> anyhow::Result<Vec<(String, Vec<String>)>> {
+    let station = args.station.as_deref().expect("station is required");
+    std::iter::once(station)
+        .chain(args.also.iter().map(String::as_str))
+        .map(|name| resolve_station(gtfs, name, aliases, args))
+        .collect()
+}
+
+/// Resolves the stations of watch mode into `state` before its first refresh, so that the
+/// user chooses among several matching stations once, and before the board takes over the
+/// screen. When the static feed cannot be loaded yet, the first refresh that loads it does.
+async fn resolve_for_watch(
+    args: &Cli,
+    client: &Client,
+    static_source: &FeedSource,
+    state: &mut WatchState,
+) -> anyhow::Result<()> {
+    let aliases = match &args.aliases {
+        Some(path) => load_aliases(path)?,
+        None => HashMap::new(),
+    };
+    let gtfs = static_feed(client, static_source, Some(&mut state.static_feed)).await?;
+    let stations = resolve_stations(&gtfs, &aliases, args)?;
+    state.stations = Some(stations.into_iter().map(|(name, _)| name).collect());
+    Ok(())
+}
+
+/// Shows the watch board on the terminal's alternate screen, with the cursor hidden, until it
+/// is dropped or the program panics, so that the shell's screen comes back as it was.
+struct AlternateScreen;
+
+impl AlternateScreen {
+    fn enter() -> Self {
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = std::io::stdout().flush();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // Restore first, or the panic message would vanish with the alternate screen.
+            AlternateScreen::restore();
+            default_hook(info);
+        }));
+        AlternateScreen
+    }
+
+    fn restore() {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        AlternateScreen::restore();
+    }
+}
+
+/// Resolves once the user presses Ctrl-C or, on Unix, the program is asked to terminate, as
+/// service managers do.
+async fn shutdown_requested() {
+    let interrupted = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            // Ctrl-C then still ends the program, only without cleaning up.
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    if let Ok(mut terminate) =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        tokio::select! {
+            () = interrupted => {}
+            _ = terminate.recv() => {}
+        }
+        return;
+    }
+    interrupted.await;
+}
+
+/// Fetches both feeds and prints the departures for the station on the command line.
+///
+/// The service day, the dates whose services run and the display window are all derived from
+/// `clock` afresh on each call, and watch mode keeps nothing from one refresh to the next but
+/// its `state`, so the board follows the system clock when it is stepped, across days included.
+/// That state holds on to the static feed, so that only the realtime feed is fetched on most
+/// refreshes.
+async fn show_departures(
+    args: &Cli,
+    client: &Client,
+    static_source: &FeedSource,
+    realtime_sources: &[FeedSource],
+    clock: &dyn Clock,
+    state: Option<&mut WatchState>,
+) -> anyhow::Result<()> {
+    let station = args.station.clone().expect("station is required");
+    let aliases = match &args.aliases {
+        Some(path) => load_aliases(path)?,
+        None => HashMap::new(),
+    };
+    // Runs over several stations keep their results apart from those of each station alone.
+    let station_names = std::iter::once(&station)
+        .chain(&args.also)
+        .map(|name| aliases.get(name).unwrap_or(name).as_str())
+        .collect::<Vec<_>>();
+    let include_past = TimeDelta::minutes(args.include_past);
+    let fallback_file = args
+        .fallback_file
+        .clone()
+        .or_else(|| default_fallback_file(&station_names.join(" + ")));
+
+    let (history, snapshots, static_cache, resolved) = match state {
+        Some(state) => (
+            Some(&mut state.delays),
+            Some(&mut state.snapshots),
+            Some(&mut state.static_feed),
+            Some(&mut state.stations),
+        ),
+        None => (None, None, None, None),
+    };
+    let max_size = realtime_size_limit(args);
+    // In watch mode, a transient failure would otherwise show stale data until the next refresh.
+    let realtime = async {
+        match args.watch {
+            Some(interval) => {
+                let budget = Duration::from_secs(interval) / 2;
+                feed::fetch_realtime_retrying(client, realtime_sources, max_size, budget, snapshots)
+                    .await
+            }
+            None => {
+                feed::fetch_realtime_merged(client, realtime_sources, max_size, snapshots).await
+            }
+        }
+    };
+    let (gtfs_static, realtime_data) =
+        join!(static_feed(client, static_source, static_cache), realtime);
+
+    let feeds = gtfs_static
+        .context("Could not load the static feed")
+        .and_then(|gtfs_static| {
+            let realtime_data = realtime_data.context("Could not load the realtime feed")?;
+            Ok((gtfs_static, realtime_data))
+        });
+
+    let (gtfs_static, realtime_data) = match feeds {
+        Ok(feeds) => feeds,
+        Err(err) => {
+            // Last resort: show what was computed the last time the feeds were reachable.
+            // The saved departures do not reach into the next service day.
+            let Some(Ok((captured, departures))) = fallback_file
+                .as_deref()
+                .filter(|_| !args.first)
+                .map(load_departures)
+            else {
+                return Err(err);
+            };
+            eprintln!("{err:#}");
+            let banner = Note {
+                template: "Data may be stale (captured {})",
+                values: vec![
+                    captured
+                        .with_timezone(&Eastern)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string(),
+                ],
+            };
+            let now = clock.now().with_timezone(&Eastern);
+            // The departures were saved unfiltered, so that this run's filters apply.
+            if !args.to.is_empty() {
+                eprintln!("WARNING: --to cannot be applied without the static feed");
+            }
+            let mut departures = departures
+                .into_iter()
+                .filter(|departure| on_routes(departure, &args.routes))
+                .filter(|departure| trip_selected(departure, args))
+                .filter(|departure| !args.only_realtime || departure.has_realtime())
+                .collect::<Vec<_>>();
+            if args.last {
+                return print_last(&departures, now, args);
+            }
+            departures.retain(|departure| departure.time >= now - include_past);
+            keep_reachable(&mut departures, now, args);
+            let feed_age = Some(now.to_utc() - captured);
+            print_output(&departures, &[], now, feed_age, &[banner], args)?;
+            if args.next && output::upcoming(&departures, now).next().is_none() {
+                bail!("No upcoming departure");
+            }
+            return Ok(());
+        }
+    };
+
+    let stations = match resolved {
+        Some(Some(names)) => names
+            .iter()
+            .map(|name| {
+                let stop_ids = stop_ids_named(&gtfs_static, name);
+                if stop_ids.is_empty() {
+                    bail!("`{name}` is no longer a station in the feed");
+                }
+                Ok((name.clone(), stop_ids))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        resolved => {
+            let stations = resolve_stations(&gtfs_static, &aliases, args)?;
+            if let Some(resolved) = resolved {
+                *resolved = Some(stations.iter().map(|(name, _)| name.clone()).collect());
+            }
+            stations
+        }
+    };
+    let stop_ids = stations
+        .iter()
+        .flat_map(|(_name, stop_ids)| stop_ids.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let captured = clock.now();
+    let now = captured.with_timezone(&feed_timezone(&gtfs_static));
+    let today = now.date_naive();
+
+    check_feed_expiry(&gtfs_static, today, args.max_age_days)?;
+    check_feed_sync(&gtfs_static, &realtime_data);
+
+    let board = if args.arrivals {
+        Board::Arrivals
+    } else if args.include_no_pickup {
+        Board::AllCalls
+    } else {
+        Board::Departures
+    };
+    if let Some(trip_id) = &args.explain {
+        return explain_trip(
+            &gtfs_static,
+            &realtime_data,
+            &stop_ids,
+            trip_id,
+            now,
+            board,
+            args,
+        );
+    }
+
+    if args.first {
+        return print_first(&gtfs_static, &stop_ids, now, args);
+    }
+
+    // Off-peak, exo's feed is often valid but empty, which is easy to mistake for an outage.
+    let mut notes = Vec::new();
+    if realtime_data.entity.is_empty() {
+        notes.push(Note {
+            template: "No realtime updates at the moment; times are as scheduled",
+            values: Vec::new(),
+        });
+    }
+
+    // The last train of the day may have left long before the usual lookback.
+    let lookback = if args.last {
+        now - service_day_start(now)
+    } else {
+        include_past
+    };
+    let mut valid_stops = upcoming_departures(
+        &gtfs_static,
+        &realtime_data,
+        &stop_ids,
+        now,
+        (lookback, args.window.map(TimeDelta::hours)),
+        board,
+        args,
+    );
+    let route_names = route_names(&gtfs_static);
+    for departure in &mut valid_stops {
+        departure.route_name = route_names
+            .get(departure.route_id.as_str())
+            .map(|name| (*name).to_owned());
+    }
+    // Saved before this run's filters, as a run without the feeds applies its own instead.
+    let mut unfiltered = fallback_file.as_ref().map(|_| valid_stops.clone());
+    for route in &args.routes {
+        if !route_names.contains_key(route.as_str())
+            && !route_names
+                .values()
+                .any(|name| name.eq_ignore_ascii_case(route))
+        {
+            eprintln!("WARNING: No route `{route}` in the static feed");
+        }
+    }
+    valid_stops.retain(|departure| on_routes(departure, &args.routes));
+    valid_stops.retain(|departure| trip_selected(departure, args));
+    if args.only_realtime {
+        valid_stops.retain(Departure::has_realtime);
+    }
+    keep_reachable(&mut valid_stops, now, args);
+    let legs = args
+        .to
+        .iter()
+        .map(|name| {
+            let name = aliases.get(name).unwrap_or(name);
+            let ids = stop_ids_named(&gtfs_static, name);
+            if ids.is_empty() {
+                bail!("`{name}` is not a station in the feed");
+            }
+            Ok((name.as_str(), ids))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some((_name, destination)) = legs.first() {
+        keep_serving(&gtfs_static, &mut valid_stops, &stop_ids, destination);
+    }
+    if let Some(name) = &args.highlight {
+        let name = aliases.get(name).unwrap_or(name);
+        let highlight = stop_ids_named(&gtfs_static, name);
+        if highlight.is_empty() {
+            bail!("`{name}` is not a station in the feed");
+        }
+        for departure in &mut valid_stops {
+            departure.highlighted =
+                arrival_at(&gtfs_static, departure, &stop_ids, &highlight).is_some();
+        }
+    }
+
+    sort_departures(&gtfs_static, &mut valid_stops, args.sort);
+    if args.verbose {
+        for departure in &valid_stops {
+            if let Some(scheduled) = gtfs_static
+                .trips
+                .get(&departure.trip_id)
+                .and_then(|trip| trip.trip_headsign.as_ref())
+                && *scheduled != departure.headsign
+            {
+                eprintln!(
+                    "Trip {}: headsign `{}` from the realtime feed, instead of `{scheduled}`",
+                    departure.trip_id, departure.headsign
+                );
+            }
+            let source = match (&departure.realtime_entity, &departure.delay_source) {
+                (Some(entity), Some(stop)) => format!("entity {entity}, stop update {stop}"),
+                (Some(entity), None) => format!("entity {entity}, no stop update with a delay"),
+                (None, _) => "none".to_owned(),
+            };
+            eprintln!(
+                "Trip {} at {}: realtime source {source}",
+                departure.trip_id,
+                departure.time.format(&args.timefmt)
+            );
+        }
+    }
+
+    match legs.as_slice() {
+        [] | [_] => {}
+        [
+            (connection_name, connection),
+            (destination_name, destination),
+        ] => {
+            let limit = args.limit.unwrap_or(usize::MAX).min(valid_stops.len());
+            print_itineraries(
+                &gtfs_static,
+                &realtime_data,
+                &valid_stops[..limit],
+                (connection_name, connection),
+                (destination_name, destination),
+                now,
+                args,
+            );
+            return Ok(());
+        }
+        _ => bail!("--to takes at most two stations, the connection then the destination"),
+    }
+
+    if args.last {
+        return print_last(&valid_stops, now, args);
+    }
+
+    let feed_age = realtime_data
+        .header
+        .timestamp
+        .and_then(|timestamp| DateTime::from_timestamp(i64::try_from(timestamp).ok()?, 0))
+        .map(|generated| captured - generated);
+    if let Some(history) = history {
+        note_delay_trends(history, &mut valid_stops);
+    }
+    print_output(&valid_stops, &stations, now, feed_age, &notes, args)?;
+
+    if let (Some(path), Some(departures)) = (&fallback_file, &mut unfiltered) {
+        sort_departures(&gtfs_static, departures, args.sort);
+        if let Err(err) = save_departures(path, captured, departures) {
+            eprintln!("Could not save results for offline use: {err:#}");
+        }
+    }
+    if args.next && output::upcoming(&valid_stops, now).next().is_none() {
+        bail!("No upcoming departure");
+    }
+    Ok(())
+}
+
+/// A random duration between zero and `max`, with millisecond resolution.
+fn random_delay(max: Duration) -> Duration {
+    // Each RandomState is seeded afresh, which is plenty for spreading out polls.
+    let random = std::hash::RandomState::new().build_hasher().finish();
+    let max_millis = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+    Duration::from_millis(random % max_millis.saturating_add(1))
+}
+
+/// Runs the command given on the command line.
+pub async fn run() -> anyhow::Result<()> {
+    let args = Cli::parse();
+    if let Some(Command::Agencies) = args.command {
+        print_agencies();
+        return Ok(());
+    }
+    let client = http_client(&args)?;
+    if args.dry_run {
+        print!("{}", dry_run(&args)?);
+        return Ok(());
+    }
+    let token = args
+        .token_file
+        .as_deref()
+        .map(read_token_file)
+        .transpose()?;
+    let (static_source, realtime_sources) = feed_sources(&args, token.as_deref())?;
+    let max_size = realtime_size_limit(&args);
+
+    if let Some(Command::Healthcheck) = args.command {
+        return healthcheck(&client, &static_source, &realtime_sources, max_size).await;
+    }
+    if let Some(Command::FeedInfo) = args.command {
+        return feed_info(&client, &static_source, &realtime_sources, max_size).await;
+    }
+
+    let Some(interval) = args.watch else {
+        let clock: &dyn Clock = match args.now {
+            Some(now) => &FixedClock(now.with_timezone(&Utc)),
+            None => &SystemClock,
+        };
+        return show_departures(
+            &args,
+            &client,
+            &static_source,
+            &realtime_sources,
+            clock,
+            None,
+        )
+        .await;
+    };
+    let interval = Duration::from_secs(interval);
+    if let Some(dir) = &args.as_of {
+        return replay(&args, &client, &static_source, dir, interval).await;
+    }
+    #[cfg(unix)]
+    let mut refresh_signal = if args.refresh_on_sigusr1 {
+        use tokio::signal::unix::{SignalKind, signal};
+        Some(signal(SignalKind::user_defined1()).context("Could not listen for SIGUSR1")?)
+    } else {
+        None
+    };
+    let mut state = WatchState::default();
+    if let Err(err) = resolve_for_watch(&args, &client, &static_source, &mut state).await {
+        eprintln!("Error: {err:#}");
+    }
+    let screen =
+        (args.output.is_none() && std::io::stdout().is_terminal()).then(AlternateScreen::enter);
+    let shutdown = shutdown_requested();
+    tokio::pin!(shutdown);
+    loop {
+        if screen.is_some() {
+            // Clear the screen so the board updates in place.
+            print!("\x1b[2J\x1b[H");
+        }
+        let refresh = show_departures(
+            &args,
+            &client,
+            &static_source,
+            &realtime_sources,
+            &SystemClock,
+            Some(&mut state),
+        );
+        tokio::select! {
+            result = refresh => {
+                if let Err(err) = result {
+                    eprintln!("Error: {err:#}");
+                }
+            }
+            () = &mut shutdown => break,
+        }
+        let timer =
+            tokio::time::sleep(interval + random_delay(Duration::from_secs(args.poll_jitter)));
+        // A pushed update refreshes the board right away.
+        let pushed = feed::next_push(&realtime_sources);
+        #[cfg(unix)]
+        if let Some(refresh_signal) = &mut refresh_signal {
+            tokio::select! {
+                () = timer => {}
+                () = pushed => {}
+                _ = refresh_signal.recv() => {}
+                () = &mut shutdown => break,
+            }
+            continue;
+        }
+        tokio::select! {
+            () = timer => {}
+            () = pushed => {}
+            () = &mut shutdown => break,
+        }
+    }
+    drop(screen);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use gtfs_realtime::trip_update::StopTimeEvent;
+    use gtfs_realtime::{FeedEntity, TripDescriptor};
+    use gtfs_structures::{Calendar, Route, Stop};
+
+    use super::*;
+
+    /// Name of the stops of the test feeds, by stop id.
+    const STATIONS: &[(&str, &str)] = &[
+        ("GC", "Gare Centrale"),
+        ("VM", "Vendôme"),
+        ("LX", "Lucien-L'Allier"),
+        ("SJ", "Saint-Jérôme"),
+    ];
+
+    /// A feed whose trips all run every day of 2026 on route `R1`, each given by its trip id
+    /// and its calls, as stop ids and seconds after midnight, and heading to its last stop.
+    fn feed(trips: &[(&str, &[(&str, u32)])]) -> Gtfs {
+        let stops = STATIONS
+            .iter()
+            .map(|(id, name)| {
+                let stop = Stop {
+                    id: (*id).to_owned(),
+                    name: Some((*name).to_owned()),
+                    ..Default::default()
+                };
+                ((*id).to_owned(), Arc::new(stop))
+            })
+            .collect::<HashMap<_, _>>();
+        let trips = trips
+            .iter()
+            .map(|(trip_id, calls)| {
+                let stop_times = calls
+                    .iter()
+                    .zip(1..)
+                    .map(|((stop_id, seconds), stop_sequence)| StopTime {
+                        stop: stops[*stop_id].clone(),
+                        arrival_time: Some(*seconds),
+                        departure_time: Some(*seconds),
+                        stop_sequence,
+                        ..Default::default()
+                    })
+                    .collect::<Vec<_>>();
+                let trip = Trip {
+                    id: (*trip_id).to_owned(),
+                    service_id: "daily".to_owned(),
+                    route_id: "R1".to_owned(),
+                    trip_headsign: stop_times
+                        .last()
+                        .and_then(|stop_time| stop_time.stop.name.clone()),
+                    stop_times,
+                    ..Default::default()
+                };
+                ((*trip_id).to_owned(), trip)
+            })
+            .collect();
+        let calendar = Calendar {
+            id: "daily".to_owned(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: true,
+            sunday: true,
+            start_date: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2026, 12, 31).unwrap(),
+        };
+        let route = Route {
+            id: "R1".to_owned(),
+            ..Default::default()
+        };
+        Gtfs {
+            stops,
+            trips,
+            calendar: HashMap::from([("daily".to_owned(), calendar)]),
+            routes: HashMap::from([("R1".to_owned(), route)]),
+            ..Default::default()
+        }
+    }
+
+    /// The current time as `show_departures` reads it from a clock standing at `time`, given in
+    /// RFC 3339.
+    fn now_at(time: &str) -> DateTime<Tz> {
+        let clock = FixedClock(DateTime::parse_from_rfc3339(time).unwrap().to_utc());
+        clock.now().with_timezone(&Eastern)
+    }
+
+    /// The arguments of a run for Gare Centrale with the options `extra`.
+    fn args(extra: &[&str]) -> Cli {
+        let args = ["train_display", "Gare Centrale"].iter().chain(extra);
+        Cli::try_parse_from(args).unwrap()
+    }
+
+    /// A realtime feed delaying each of `delays`, given by trip id and delay in seconds, from
+    /// its first stop on.
+    fn delays(delays: &[(&str, i32)]) -> FeedMessage {
+        let entity = delays
+            .iter()
+            .map(|(trip_id, delay)| FeedEntity {
+                id: format!("e-{trip_id}"),
+                trip_update: Some(TripUpdate {
+                    trip: TripDescriptor {
+                        trip_id: Some((*trip_id).to_owned()),
+                        ..Default::default()
+                    },
+                    stop_time_update: vec![StopTimeUpdate {
+                        stop_sequence: Some(1),
+                        departure: Some(StopTimeEvent {
+                            delay: Some(*delay),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect();
+        FeedMessage {
+            entity,
+            ..Default::default()
+        }
+    }
+
+    /// The departures from Gare Centrale at `now` given `realtime_data`, in the order of `--sort`.
+    fn board_with(
+        gtfs: &Gtfs,
+        realtime_data: &FeedMessage,
+        now: DateTime<Tz>,
+        args: &Cli,
+    ) -> Vec<Departure> {
+        let mut departures = upcoming_departures(
+            gtfs,
+            realtime_data,
+            &["GC".to_owned()],
+            now,
+            (
+                TimeDelta::minutes(args.include_past),
+                args.window.map(TimeDelta::hours),
+            ),
+            Board::Departures,
+            args,
+        );
+        sort_departures(gtfs, &mut departures, args.sort);
+        departures
+    }
+
+    /// The departures from Gare Centrale at `now` without realtime data.
+    fn board(gtfs: &Gtfs, now: DateTime<Tz>, args: &Cli) -> Vec<Departure> {
+        board_with(gtfs, &FeedMessage::default(), now, args)
+    }
+
+    #[test]
+    fn trip_without_headsign_heads_to_its_last_stop() {
+        let mut gtfs = feed(&[
+            ("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)]),
+            ("t2", &[("GC", 10 * 3600), ("LX", 11 * 3600)]),
+        ]);
+        gtfs.trips.get_mut("t1").unwrap().trip_headsign = None;
+        gtfs.trips.get_mut("t2").unwrap().trip_headsign = Some(String::new());
+        let departures = board(&gtfs, now_at("2026-06-01T07:00:00-04:00"), &args(&[]));
+        let headsigns = departures
+            .iter()
+            .map(|departure| departure.headsign.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(headsigns, ["Saint-Jérôme", "Lucien-L'Allier"]);
+    }
+
+    #[test]
+    fn midnight_departure_is_on_its_service_date() {
+        let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+        assert_eq!(
+            scheduled_time(date, 0, Eastern),
+            Some(now_at("2026-06-02T00:00:00-04:00"))
+        );
+        assert_eq!(
+            scheduled_time(date, 24 * 3600, Eastern),
+            Some(now_at("2026-06-03T00:00:00-04:00"))
+        );
+    }
+
+    #[test]
+    fn midnight_departure_is_listed_until_it_leaves() {
+        let gtfs = feed(&[("t1", &[("GC", 0), ("SJ", 3600)])]);
+        for now in ["2026-06-01T23:50:00-04:00", "2026-06-02T00:00:00-04:00"] {
+            let departures = board(&gtfs, now_at(now), &args(&[]));
+            let [departure] = departures.as_slice() else {
+                panic!("expected one departure at {now}, got {departures:?}");
+            };
+            assert_eq!(departure.time, now_at("2026-06-02T00:00:00-04:00"));
+            assert_eq!(departure.service_date, NaiveDate::from_ymd_opt(2026, 6, 2));
+        }
+        let departures = board(&gtfs, now_at("2026-06-02T00:00:01-04:00"), &args(&[]));
+        assert!(departures.is_empty(), "{departures:?}");
+    }
+
+    #[test]
+    fn delay_is_applied_once() {
+        let scheduled = now_at("2026-06-01T08:00:00-04:00");
+        assert_eq!(
+            delayed(scheduled, Some(300)),
+            Some(now_at("2026-06-01T08:05:00-04:00"))
+        );
+        assert_eq!(delayed(scheduled, None), Some(scheduled));
+
+        let gtfs = feed(&[("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)])]);
+        let realtime_data = delays(&[("t1", 300)]);
+        let now = now_at("2026-06-01T07:00:00-04:00");
+        // Computing the board again must not delay the train any further.
+        for _refresh in 0..2 {
+            let departures = board_with(&gtfs, &realtime_data, now, &args(&[]));
+            let [departure] = departures.as_slice() else {
+                panic!("expected one departure, got {departures:?}");
+            };
+            assert_eq!(departure.delay, Some(300));
+            assert_eq!(departure.time, now_at("2026-06-01T08:05:00-04:00"));
+        }
+    }
+
+    /// Trip ids of `departures`, in order.
+    fn trip_ids(departures: &[Departure]) -> Vec<&str> {
+        departures
+            .iter()
+            .map(|departure| departure.trip_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn service_day_when_clocks_spring_forward() {
+        // On 2026-03-08, clocks jump from 02:00 EST to 03:00 EDT.
+        let date = NaiveDate::from_ymd_opt(2026, 3, 8).unwrap();
+        let early = scheduled_time(date, 5400, Eastern).unwrap();
+        let late = scheduled_time(date, 4 * 3600, Eastern).unwrap();
+        assert_eq!(early, now_at("2026-03-08T00:30:00-05:00"));
+        assert_eq!(late, now_at("2026-03-08T04:00:00-04:00"));
+        assert_eq!(late - early, TimeDelta::minutes(150));
+        assert_eq!(
+            local_instant(date, DAY_TRANSITION, Eastern),
+            now_at("2026-03-08T03:00:00-04:00")
+        );
+
+        let gtfs = feed(&[
+            ("early", &[("GC", 5400), ("SJ", 9000)]),
+            ("late", &[("GC", 4 * 3600), ("SJ", 5 * 3600)]),
+        ]);
+        let before = board(&gtfs, now_at("2026-03-08T00:00:00-05:00"), &args(&[]));
+        assert_eq!(trip_ids(&before), ["early"]);
+        // The early train of the next service day closes the board.
+        let after = board(&gtfs, now_at("2026-03-08T03:30:00-04:00"), &args(&[]));
+        assert_eq!(trip_ids(&after), ["late", "early"]);
+        assert_eq!(after[0].time, late);
+        assert_eq!(after[1].service_date, NaiveDate::from_ymd_opt(2026, 3, 9));
+    }
+
+    #[test]
+    fn service_day_when_clocks_fall_back() {
+        // On 2026-11-01, clocks go back from 02:00 EDT to 01:00 EST.
+        let date = NaiveDate::from_ymd_opt(2026, 11, 1).unwrap();
+        let early = scheduled_time(date, 5400, Eastern).unwrap();
+        let late = scheduled_time(date, 3 * 3600, Eastern).unwrap();
+        assert_eq!(early, now_at("2026-11-01T01:30:00-05:00"));
+        assert_eq!(late, now_at("2026-11-01T03:00:00-05:00"));
+        assert_eq!(late - early, TimeDelta::minutes(90));
+        assert_eq!(
+            local_instant(date, DAY_TRANSITION, Eastern),
+            now_at("2026-11-01T02:00:00-05:00")
+        );
+
+        let gtfs = feed(&[
+            ("early", &[("GC", 5400), ("SJ", 9000)]),
+            ("late", &[("GC", 3 * 3600), ("SJ", 4 * 3600)]),
+        ]);
+        // The first time clocks read 01:45, then an hour later once they went back.
+        for now in ["2026-11-01T01:45:00-04:00", "2026-11-01T01:15:00-05:00"] {
+            let before = board(&gtfs, now_at(now), &args(&[]));
+            assert_eq!(trip_ids(&before), ["early"], "at {now}");
+        }
+        let after = board(&gtfs, now_at("2026-11-01T02:30:00-05:00"), &args(&[]));
+        assert_eq!(trip_ids(&after), ["late", "early"]);
+        assert_eq!(after[0].time, late);
+        assert_eq!(after[1].service_date, NaiveDate::from_ymd_opt(2026, 11, 2));
+    }
+
+    #[test]
+    fn delayed_train_sinks_below_later_one() {
+        let gtfs = feed(&[
+            ("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)]),
+            ("t2", &[("GC", 8 * 3600 + 600), ("SJ", 9 * 3600 + 600)]),
+        ]);
+        let now = now_at("2026-06-01T07:00:00-04:00");
+        let on_time = board(&gtfs, now, &args(&[]));
+        assert_eq!(trip_ids(&on_time), ["t1", "t2"]);
+        let late = board_with(&gtfs, &delays(&[("t1", 20 * 60)]), now, &args(&[]));
+        assert_eq!(trip_ids(&late), ["t2", "t1"]);
+    }
+
+    #[test]
+    fn board_follows_clock_across_day_transition() {
+        let gtfs = feed(&[
+            (
+                "owl",
+                &[("GC", 25 * 3600 + 59 * 60 + 30), ("SJ", 27 * 3600)],
+            ),
+            ("morning", &[("GC", 6 * 3600), ("SJ", 7 * 3600)]),
+        ]);
+        let args = args(&[]);
+        // As in watch mode, each refresh reads the clock again, here stepped by two minutes.
+        let before = board(&gtfs, now_at("2026-06-02T01:59:00-04:00"), &args);
+        assert_eq!(trip_ids(&before), ["owl"]);
+        assert_eq!(before[0].service_date, NaiveDate::from_ymd_opt(2026, 6, 1));
+        let after = board(&gtfs, now_at("2026-06-02T02:01:00-04:00"), &args);
+        assert_eq!(trip_ids(&after), ["morning", "owl"]);
+        assert!(
+            after
+                .iter()
+                .all(|departure| departure.service_date == NaiveDate::from_ymd_opt(2026, 6, 2))
+        );
+    }
+
+    #[test]
+    fn window_includes_both_its_ends() {
+        let listed =
+            |time: &str, now: &str| in_window(now_at(time), now_at(now), TimeDelta::zero(), None);
+        // A train leaving right now is still listed, one gone a second ago is not.
+        assert!(listed(
+            "2026-06-01T17:42:00-04:00",
+            "2026-06-01T17:42:00-04:00"
+        ));
+        assert!(!listed(
+            "2026-06-01T17:41:59-04:00",
+            "2026-06-01T17:42:00-04:00"
+        ));
+        // The window runs up to the day transition included.
+        assert!(listed(
+            "2026-06-02T02:00:00-04:00",
+            "2026-06-02T01:00:00-04:00"
+        ));
+        assert!(!listed(
+            "2026-06-02T02:00:01-04:00",
+            "2026-06-02T01:00:00-04:00"
+        ));
+        assert!(listed(
+            "2026-06-02T02:00:00-04:00",
+            "2026-06-01T17:42:00-04:00"
+        ));
+        // At the transition itself the new day starts, its train at 02:00 staying listed.
+        let transition = now_at("2026-06-02T02:00:00-04:00");
+        assert_eq!(service_day_start(transition), transition);
+        assert!(listed(
+            "2026-06-02T02:00:00-04:00",
+            "2026-06-02T02:00:00-04:00"
+        ));
+        assert!(listed(
+            "2026-06-03T02:00:00-04:00",
+            "2026-06-02T02:00:00-04:00"
+        ));
+        assert!(!listed(
+            "2026-06-03T02:00:01-04:00",
+            "2026-06-02T02:00:00-04:00"
+        ));
+        assert_eq!(
+            service_day_start(now_at("2026-06-02T01:59:59-04:00")),
+            now_at("2026-06-01T02:00:00-04:00")
+        );
+    }
+
+    #[test]
+    fn daily_trip_is_listed_once() {
+        let gtfs = feed(&[
+            ("morning", &[("GC", 11 * 3600), ("SJ", 12 * 3600)]),
+            ("midday", &[("GC", 13 * 3600), ("SJ", 14 * 3600)]),
+            ("owl", &[("GC", 25 * 3600), ("SJ", 26 * 3600)]),
+        ]);
+        let now = now_at("2026-06-01T12:00:00-04:00");
+        let departures = board(&gtfs, now, &args(&[]));
+        assert_eq!(trip_ids(&departures), ["midday", "owl"]);
+
+        // Looking back a whole day reaches yesterday's trips, which belong to an earlier
+        // service day, last night's train included.
+        let departures = board(&gtfs, now, &args(&["--include-past", "1440"]));
+        assert_eq!(trip_ids(&departures), ["morning", "midday", "owl"]);
+        assert!(
+            departures
+                .iter()
+                .all(|departure| departure.service_date == NaiveDate::from_ymd_opt(2026, 6, 1))
+        );
+
+        let debug = args(&["--include-past", "1440", "--no-dedup-dates"]);
+        let departures = board(&gtfs, now, &debug);
+        assert_eq!(
+            trip_ids(&departures),
+            ["midday", "owl", "morning", "midday", "owl"]
+        );
+    }
+
+    #[test]
+    fn route_order_does_not_depend_on_input_order() {
+        let mut gtfs = Gtfs::default();
+        for (id, order) in [("R1", Some(2)), ("R2", Some(1)), ("R3", None), ("R4", None)] {
+            let route = Route {
+                id: id.to_owned(),
+                order,
+                ..Default::default()
+            };
+            gtfs.routes.insert(id.to_owned(), route);
+        }
+        let departures = [
+            ("a", "R3", "08:00"),
+            ("b", "R1", "08:00"),
+            ("c", "R4", "07:00"),
+            ("d", "R2", "09:00"),
+            ("e", "R1", "07:30"),
+            ("f", "R3", "08:00"),
+        ]
+        .map(|(trip_id, route_id, time)| {
+            let time = now_at(&format!("2026-06-01T{time}:00-04:00"));
+            Departure::new(
+                trip_id.into(),
+                route_id.into(),
+                time,
+                "Gare Centrale".into(),
+            )
+        });
+        // Whatever order the trips come out of the feed's maps in.
+        for rotation in 0..departures.len() {
+            for reversed in [false, true] {
+                let mut sorted = departures.to_vec();
+                sorted.rotate_left(rotation);
+                if reversed {
+                    sorted.reverse();
+                }
+                sort_departures(&gtfs, &mut sorted, SortOrder::Route);
+                assert_eq!(trip_ids(&sorted), ["d", "e", "b", "a", "f", "c"]);
+            }
+        }
+    }
+
+    /// A stop update for the call at `stop_sequence` with `relationship` and no time event.
+    fn bare_stop_update(stop_sequence: u32, relationship: StopRelationship) -> StopTimeUpdate {
+        let mut stop = StopTimeUpdate {
+            stop_sequence: Some(stop_sequence),
+            ..Default::default()
+        };
+        stop.set_schedule_relationship(relationship);
+        stop
+    }
+
+    #[test]
+    fn skipped_and_no_data_updates_without_times() {
+        let gtfs = feed(&[
+            (
+                "t1",
+                &[
+                    ("VM", 7 * 3600),
+                    ("GC", 8 * 3600),
+                    ("LX", 8 * 3600 + 600),
+                    ("SJ", 9 * 3600),
+                ],
+            ),
+            (
+                "t2",
+                &[("VM", 7 * 3600), ("GC", 8 * 3600 + 300), ("SJ", 9 * 3600)],
+            ),
+        ]);
+        let mut realtime_data = delays(&[("t1", 120), ("t2", 300)]);
+        let [first, second] = &mut realtime_data.entity[..] else {
+            unreachable!();
+        };
+        let t1 = first.trip_update.as_mut().unwrap();
+        t1.stop_time_update
+            .push(bare_stop_update(2, StopRelationship::Skipped));
+        t1.stop_time_update
+            .push(bare_stop_update(4, StopRelationship::NoData));
+        let t2 = second.trip_update.as_mut().unwrap();
+        t2.stop_time_update
+            .push(bare_stop_update(2, StopRelationship::NoData));
+
+        // A skipped stop has no delay but doesn't stop its propagation; NO_DATA does.
+        let timeline = delay_timeline(t1, &gtfs.trips["t1"], false)
+            .into_iter()
+            .map(|prediction| prediction.map(|prediction| prediction.delay))
+            .collect::<Vec<_>>();
+        assert_eq!(timeline, [Some(120), None, Some(120), None]);
+
+        // t1 skips the station, and t2 leaves it as scheduled.
+        let departures = board_with(
+            &gtfs,
+            &realtime_data,
+            now_at("2026-06-01T07:00:00-04:00"),
+            &args(&[]),
+        );
+        assert_eq!(trip_ids(&departures), ["t2"]);
+        assert_eq!(departures[0].delay, None);
+        assert_eq!(departures[0].time, now_at("2026-06-01T08:05:00-04:00"));
+    }
+
+    #[test]
+    fn limit_counts_only_trains_to_the_destination() {
+        let gtfs = feed(&[
+            (
+                "t1",
+                &[("GC", 8 * 3600), ("LX", 8 * 3600 + 600), ("SJ", 9 * 3600)],
+            ),
+            ("t2", &[("GC", 9 * 3600), ("VM", 9 * 3600 + 600)]),
+            ("t3", &[("GC", 10 * 3600), ("VM", 10 * 3600 + 600)]),
+            ("t4", &[("GC", 11 * 3600), ("SJ", 12 * 3600)]),
+            ("t5", &[("GC", 12 * 3600), ("VM", 12 * 3600 + 600)]),
+            (
+                "t6",
+                &[
+                    ("GC", 13 * 3600),
+                    ("LX", 13 * 3600 + 600),
+                    ("SJ", 14 * 3600),
+                ],
+            ),
+        ]);
+        let args = args(&["--to", "Saint-Jérôme", "--limit", "2"]);
+        let mut departures = board(&gtfs, now_at("2026-06-01T07:00:00-04:00"), &args);
+        keep_serving(
+            &gtfs,
+            &mut departures,
+            &["GC".to_owned()],
+            &["SJ".to_owned()],
+        );
+        let listed = within_limits(departures.iter(), &HashMap::new(), args.limit);
+        assert_eq!(trip_ids(&listed), ["t1", "t4"]);
+        assert_eq!(listed[1].arrival, Some(now_at("2026-06-01T12:00:00-04:00")));
+    }
+
+    #[test]
+    fn nameless_stops_are_skipped_when_matching_stations() {
+        let mut gtfs = feed(&[("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)])]);
+        let entrance = Stop {
+            id: "GC-E".to_owned(),
+            code: Some("61".to_owned()),
+            ..Default::default()
+        };
+        let platform = Stop {
+            code: Some("61".to_owned()),
+            ..(*gtfs.stops["GC"]).clone()
+        };
+        gtfs.stops.insert("GC-E".to_owned(), Arc::new(entrance));
+        gtfs.stops.insert("GC".to_owned(), Arc::new(platform));
+
+        assert_eq!(
+            station_with_code(&gtfs, "61").as_deref(),
+            Some("Gare Centrale")
+        );
+        assert_eq!(station_with_code(&gtfs, "62"), None);
+        assert_eq!(stop_ids_named(&gtfs, "Gare Centrale"), ["GC"]);
+        assert_eq!(
+            disambiguate_station(&gtfs, "centrale").unwrap(),
+            "Gare Centrale"
+        );
+        let (name, stop_ids) =
+            resolve_station(&gtfs, "61", &HashMap::new(), &args(&["--by-code"])).unwrap();
+        assert_eq!(
+            (name.as_str(), stop_ids),
+            ("Gare Centrale", vec!["GC".to_owned()])
+        );
+    }
+
+    #[test]
+    fn pages_split_the_rendered_board() {
+        let board = "3 departures\n07:00 t1\n07:20 t2\n07:40 t3\n".to_owned();
+        assert_eq!(
+            into_pages(board.clone(), 3),
+            ["3 departures\n07:00 t1\n07:20 t2\n", "07:40 t3\n"]
+        );
+        assert_eq!(into_pages(board.clone(), 4), [board]);
+        assert_eq!(into_pages(String::new(), 2), [""]);
+    }
+
+    #[test]
+    fn notes_are_written_with_the_board() {
+        let gtfs = feed(&[("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)])]);
+        let now = now_at("2026-06-01T07:00:00-04:00");
+        let note = Note {
+            template: "Data may be stale (captured {})",
+            values: vec!["2026-06-01 06:55".to_owned()],
+        };
+        let path = std::env::temp_dir().join(format!("notes-{}.txt", std::process::id()));
+        let output = path.to_str().unwrap();
+        let human = args(&["--output", output, "--lang", "fr", "--color", "never"]);
+        print_output(&board(&gtfs, now, &human), &[], now, None, &[note], &human).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            written.starts_with("Données possiblement périmées (captées le 2026-06-01 06:55)\n"),
+            "{written}"
+        );
+        let note = Note {
+            template: "No realtime updates at the moment; times are as scheduled",
+            values: Vec::new(),
+        };
+        let json = args(&["--output", output, "--format", "json"]);
+        print_output(&board(&gtfs, now, &json), &[], now, None, &[note], &json).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            written.contains(
+                r#""notes":["No realtime updates at the moment; times are as scheduled"]"#
+            ),
+            "{written}"
+        );
+        let note = Note {
+            template: "No realtime updates at the moment; times are as scheduled",
+            values: Vec::new(),
+        };
+        let ndjson = args(&["--output", output, "--format", "ndjson"]);
+        print_output(
+            &board(&gtfs, now, &ndjson),
+            &[],
+            now,
+            None,
+            &[note],
+            &ndjson,
+        )
+        .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written.lines().next(),
+            Some(r#"{"note":"No realtime updates at the moment; times are as scheduled"}"#)
+        );
+        assert_eq!(written.lines().count(), 2);
+    }
+
+    #[test]
+    fn boards_list_the_trains_riders_can_take_and_flag_restrictions() {
+        let mut gtfs = feed(&[
+            ("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)]),
+            ("pickup", &[("GC", 8 * 3600 + 600), ("SJ", 9 * 3600)]),
+            ("drop_off", &[("GC", 8 * 3600 + 1200), ("SJ", 9 * 3600)]),
+        ]);
+        gtfs.trips.get_mut("pickup").unwrap().stop_times[0].drop_off_type =
+            PickupDropOffType::NotAvailable;
+        gtfs.trips.get_mut("drop_off").unwrap().stop_times[0].pickup_type =
+            PickupDropOffType::NotAvailable;
+        let now = now_at("2026-06-01T07:00:00-04:00");
+        let listed = |board| {
+            let args = args(&[]);
+            let mut departures = upcoming_departures(
+                &gtfs,
+                &FeedMessage::default(),
+                &["GC".to_owned()],
+                now,
+                (TimeDelta::zero(), None),
+                board,
+                &args,
+            );
+            sort_departures(&gtfs, &mut departures, args.sort);
+            departures
+                .into_iter()
+                .map(|departure| (departure.trip_id, departure.restriction))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            listed(Board::Departures),
+            [
+                ("t1".to_owned(), None),
+                ("pickup".to_owned(), Some("pickup only"))
+            ]
+        );
+        assert_eq!(
+            listed(Board::Arrivals),
+            [
+                ("t1".to_owned(), None),
+                ("drop_off".to_owned(), Some("drop-off only"))
+            ]
+        );
+        assert_eq!(
+            listed(Board::AllCalls),
+            [
+                ("t1".to_owned(), None),
+                ("pickup".to_owned(), Some("pickup only")),
+                ("drop_off".to_owned(), Some("drop-off only"))
+            ]
+        );
+        let args = args(&["--lang", "en"]);
+        let context = RenderContext {
+            now,
+            feed_age: None,
+            station: "Gare Centrale",
+            color: false,
+            time_format: &args.timefmt,
+            lang: Lang::En,
+            notes: &[],
+        };
+        let rendered = output_format(&args).render(&board(&gtfs, now, &args), &context);
+        let pickup_line = rendered
+            .lines()
+            .find(|line| line.contains("08:10"))
+            .unwrap();
+        assert!(pickup_line.contains("pickup only"), "{rendered}");
+    }
+
+    #[test]
+    fn dry_run_lists_the_options_and_reports_an_unreadable_token_file() {
+        let report = dry_run(&args(&[
+            "--token-file",
+            "/nonexistent/token",
+            "--limit",
+            "3",
+            "--route",
+            "11",
+            "--exclude-trip",
+            "t2",
+            "--arrivals",
+            "--also",
+            "Vendôme",
+            "--walk-time",
+            "4",
+        ]))
+        .unwrap();
+        let lines = report.lines().collect::<Vec<_>>();
+        assert!(
+            lines.iter().any(|line| line
+                .starts_with("token: unreadable, Could not read token file /nonexistent/token")),
+            "{report}"
+        );
+        for line in [
+            "station: Gare Centrale",
+            "also: Vendôme",
+            "board: arrivals",
+            "route: 11",
+            "exclude trip: t2",
+            "walk time: 4 min, boarding buffer: 0 min",
+            "limit: 3",
+        ] {
+            assert!(lines.contains(&line), "{line} missing from {report}");
+        }
+    }
+
+    #[test]
+    fn times_are_read_in_the_feed_timezone() {
+        let mut gtfs = feed(&[("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)])]);
+        assert_eq!(feed_timezone(&gtfs), Eastern);
+        let agency = |timezone: &str| gtfs_structures::Agency {
+            timezone: timezone.to_owned(),
+            ..Default::default()
+        };
+        gtfs.agencies = vec![agency("Mars/Olympus_Mons")];
+        assert_eq!(feed_timezone(&gtfs), Eastern);
+        gtfs.agencies = vec![agency("America/Vancouver")];
+        let tz = feed_timezone(&gtfs);
+        assert_eq!(tz, chrono_tz::America::Vancouver);
+        // 07:00 in Vancouver, before its 08:00 departure and long after Montréal's.
+        let now = now_at("2026-06-01T10:00:00-04:00").with_timezone(&tz);
+        let departures = board(&gtfs, now, &args(&[]));
+        assert_eq!(trip_ids(&departures), ["t1"]);
+        assert_eq!(departures[0].time.to_rfc3339(), "2026-06-01T08:00:00-07:00");
+    }
+
+    #[test]
+    fn minute_options_are_at_most_a_day() {
+        for option in [
+            "--include-past",
+            "--separate-imminent",
+            "--walk-time",
+            "--min-connection",
+        ] {
+            for minutes in ["-1", "1441", "9999999999999999"] {
+                let parsed = Cli::try_parse_from(["train_display", option, minutes, "GC"]);
+                assert!(parsed.is_err(), "{option} {minutes}");
+            }
+            assert!(Cli::try_parse_from(["train_display", option, "1440", "GC"]).is_ok());
+        }
+        let buffer = ["train_display", "--walk-time", "5", "--boarding-buffer"];
+        assert!(Cli::try_parse_from(buffer.iter().chain(&["-1", "GC"])).is_err());
+        assert!(Cli::try_parse_from(buffer.iter().chain(&["1440", "GC"])).is_ok());
+    }
+
+    #[test]
+    fn trains_continue_as_the_next_trip_of_their_block() {
+        let mut gtfs = feed(&[
+            ("t1", &[("GC", 8 * 3600), ("SJ", 9 * 3600)]),
+            ("earlier", &[("SJ", 6 * 3600), ("VM", 7 * 3600)]),
+            ("next", &[("SJ", 9 * 3600 + 600), ("LX", 10 * 3600)]),
+            ("later", &[("SJ", 12 * 3600), ("VM", 13 * 3600)]),
+            ("t2", &[("GC", 8 * 3600 + 600), ("SJ", 9 * 3600)]),
+        ]);
+        for trip_id in ["t1", "earlier", "next", "later"] {
+            gtfs.trips.get_mut(trip_id).unwrap().block_id = Some("b1".to_owned());
+        }
+        let departures = board(&gtfs, now_at("2026-06-01T07:00:00-04:00"), &args(&[]));
+        let continues_as = departures
+            .iter()
+            .map(|departure| departure.continues_as.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(continues_as, [Some("Lucien-L'Allier"), None]);
+    }
+
+    #[test]
+    fn alias_and_route_limit_files_share_their_syntax() {
+        let path = std::env::temp_dir().join(format!("key-values-{}.txt", std::process::id()));
+        let load = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            (load_aliases(&path), load_route_limits(&path))
+        };
+        let (aliases, limits) = load("# comment\ngc, centrale = \"Gare Centrale\" # note\n\n");
+        assert_eq!(
+            aliases.unwrap(),
+            HashMap::from([
+                ("gc".to_owned(), "Gare Centrale".to_owned()),
+                ("centrale".to_owned(), "Gare Centrale".to_owned()),
+            ])
+        );
+        let location = format!("{}:2", path.display());
+        assert_eq!(
+            limits.unwrap_err().to_string(),
+            format!("{location}: invalid count (invalid digit found in string)")
+        );
+        let (aliases, limits) = load("r11, r12 = 3\nr13 = 1\n");
+        assert_eq!(
+            limits.unwrap(),
+            HashMap::from([
+                ("r11".to_owned(), 3),
+                ("r12".to_owned(), 3),
+                ("r13".to_owned(), 1)
+            ])
+        );
+        assert_eq!(aliases.unwrap()["r13"], "1");
+        let errors = |contents| {
+            let (aliases, limits) = load(contents);
+            (
+                aliases.unwrap_err().to_string(),
+                limits.unwrap_err().to_string(),
+            )
+        };
+        assert_eq!(
+            errors("\nr11 3\n"),
+            (
+                format!("{location}: expected `alias = \"Station\"`"),
+                format!("{location}: expected `route = count`")
+            )
+        );
+        assert_eq!(
+            errors("\nr11, = 3\n"),
+            (
+                format!("{location}: empty alias"),
+                format!("{location}: empty route")
+            )
+        );
+        assert_eq!(
+            errors("r11 = 3\nr11 = 4\n"),
+            (
+                format!("{location}: alias `r11` given twice"),
+                format!("{location}: route `r11` given twice")
+            )
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn service_reasons_agree_with_the_services_running() {
+        let mut gtfs = feed(&[]);
+        let date = |day| NaiveDate::from_ymd_opt(2026, 6, day).unwrap();
+        let exception = |service_id: &str, day, exception_type| gtfs_structures::CalendarDate {
+            service_id: service_id.to_owned(),
+            date: date(day),
+            exception_type,
+        };
+        gtfs.calendar.get_mut("daily").unwrap().sunday = false;
+        gtfs.calendar_dates = HashMap::from([
+            (
+                "daily".to_owned(),
+                vec![
+                    exception("daily", 2, Exception::Deleted),
+                    // Corrected by a later entry.
+                    exception("daily", 3, Exception::Deleted),
+                    exception("daily", 3, Exception::Added),
+                ],
+            ),
+            (
+                "special".to_owned(),
+                vec![exception("special", 6, Exception::Added)],
+            ),
+        ]);
+        let day = |day| {
+            let mut running = service_ids_for(&gtfs, date(day));
+            running.sort();
+            let reasons = ["daily", "special"].map(|service_id| {
+                let (runs, reason) = service_reason(&gtfs, service_id, date(day));
+                assert_eq!(runs, running.iter().any(|id| id == service_id));
+                reason
+            });
+            (running, reasons)
+        };
+        assert_eq!(
+            day(1),
+            (
+                vec!["daily".to_owned()],
+                [
+                    "calendar runs on Mondays, 2026-01-01 to 2026-12-31".to_owned(),
+                    "no calendar entry".to_owned()
+                ]
+            )
+        );
+        assert_eq!(day(2).1[0], "removed by calendar_dates");
+        assert_eq!(day(3).1[0], "added by calendar_dates");
+        assert_eq!(day(6).0, ["daily", "special"]);
+        assert_eq!(day(6).1[1], "added by calendar_dates");
+        assert_eq!(day(7).1[0], "calendar does not run on Sundays");
+        assert!(day(7).0.is_empty());
+    }
+}
@@ -0,0 +1,832 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+use chrono::{DateTime, TimeDelta};
+use chrono_tz::Tz;
+
+use crate::lang::Lang;
+use crate::{Column, Departure, json};
+
+/// What every output format may need besides the departures themselves.
+pub struct RenderContext<'a> {
+    pub now: DateTime<Tz>,
+    /// How old the realtime data is, when known
+    pub feed_age: Option<TimeDelta>,
+    /// The station as given on the command line
+    pub station: &'a str,
+    /// Whether ANSI colors may be used
+    pub color: bool,
+    /// strftime pattern for the displayed times
+    pub time_format: &'a str,
+    /// Language of the labels of human output
+    pub lang: Lang,
+    /// Lines about the board as a whole, shown above the departures
+    pub notes: &'a [Note],
+}
+
+/// A line about the board as a whole, like how old its data is.
+pub struct Note {
+    /// The English text, with `{}` where each of `values` goes
+    pub template: &'static str,
+    pub values: Vec<String>,
+}
+
+impl Note {
+    /// `template` in `lang`, filled with `values`.
+    pub fn text(&self, lang: Lang) -> String {
+        let values = self
+            .values
+            .iter()
+            .map(|value| value as &dyn std::fmt::Display)
+            .collect::<Vec<_>>();
+        lang.fill(self.template, &values)
+    }
+}
+
+/// A way of printing departures.
+pub trait OutputFormat {
+    /// Renders `departures`, in the order given, as the full text to print.
+    fn render(&self, departures: &[Departure], ctx: &RenderContext) -> String;
+}
+
+/// The departures still to come at `now`, in the order given, leaving out cancelled trains.
+pub fn upcoming(departures: &[Departure], now: DateTime<Tz>) -> impl Iterator<Item = &Departure> {
+    departures
+        .iter()
+        .filter(move |departure| !departure.cancelled && departure.time >= now)
+}
+
+/// How late a departure is, as shown by its color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DelayClass {
+    OnTime,
+    Minor,
+    Major,
+    Cancelled,
+}
+
+impl DelayClass {
+    /// Classifies a departure given the minor and major delay thresholds, in minutes.
+    fn of(departure: &Departure, minor_minutes: i64, major_minutes: i64) -> Self {
+        let delay_minutes = i64::from(departure.delay.unwrap_or(0)) / 60;
+        if departure.cancelled {
+            DelayClass::Cancelled
+        } else if delay_minutes >= major_minutes {
+            DelayClass::Major
+        } else if delay_minutes >= minor_minutes {
+            DelayClass::Minor
+        } else {
+            DelayClass::OnTime
+        }
+    }
+
+    /// ANSI SGR parameters used to render departures of this class.
+    fn ansi_style(self) -> &'static str {
+        match self {
+            DelayClass::OnTime => "32",
+            DelayClass::Minor => "33",
+            DelayClass::Major => "31",
+            DelayClass::Cancelled => "9;31",
+        }
+    }
+
+    /// Fill color of the delay of departures of this class on the SVG board.
+    fn svg_fill(self) -> &'static str {
+        match self {
+            DelayClass::OnTime => "#2e7d32",
+            DelayClass::Minor => "#f9a825",
+            DelayClass::Major | DelayClass::Cancelled => "#c62828",
+        }
+    }
+}
+
+/// How much a realtime prediction can be trusted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+impl Confidence {
+    /// Uncertainty, in seconds, up to which a prediction can be highly trusted.
+    const HIGH_UNCERTAINTY: i32 = 60;
+    /// Uncertainty, in seconds, beyond which a prediction deserves little trust.
+    const LOW_UNCERTAINTY: i32 = 300;
+    /// Feed age, in seconds, up to which a prediction can be highly trusted.
+    const HIGH_AGE: i64 = 120;
+    /// Feed age, in seconds, beyond which a prediction deserves little trust.
+    const LOW_AGE: i64 = 600;
+
+    /// Rates a prediction given its `uncertainty` in seconds and the age of the feed, either
+    /// of which may be unknown; unknown values never rate as high.
+    pub fn of(uncertainty: Option<i32>, feed_age: Option<TimeDelta>) -> Self {
+        let age = feed_age.map(|age| age.num_seconds());
+        if uncertainty.is_some_and(|uncertainty| uncertainty > Self::LOW_UNCERTAINTY)
+            || age.is_some_and(|age| age > Self::LOW_AGE)
+        {
+            Confidence::Low
+        } else if uncertainty.is_some_and(|uncertainty| uncertainty <= Self::HIGH_UNCERTAINTY)
+            && age.is_some_and(|age| age <= Self::HIGH_AGE)
+        {
+            Confidence::High
+        } else {
+            Confidence::Medium
+        }
+    }
+
+    /// The confidence of `departure`'s delay, if it has a realtime prediction at all.
+    fn of_departure(departure: &Departure, ctx: &RenderContext) -> Option<Self> {
+        departure.delay?;
+        Some(Self::of(departure.uncertainty, ctx.feed_age))
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Confidence::High => "high",
+            Confidence::Medium => "medium",
+            Confidence::Low => "low",
+        }
+    }
+}
+
+/// One readable line per departure.
+pub struct Human {
+    /// Prefix approximate times with `~`
+    pub mark_approximate: bool,
+    /// Delay, in minutes, from which a departure is colored as slightly late
+    pub minor_delay: i64,
+    /// Delay, in minutes, from which a departure is colored as very late
+    pub major_delay: i64,
+    /// Note the trip each train continues as, and its block
+    pub through_running: bool,
+    /// Print a separator after the trains leaving within this many minutes
+    pub separate_imminent: Option<i64>,
+    /// Fields to print instead of the default layout, when not empty
+    pub columns: Vec<Column>,
+    /// List the times for each headsign on a single line
+    pub compact_headsigns: bool,
+    /// Note how many stops each train makes before its arrival
+    pub stop_count: bool,
+    /// Follow each departure with the delay read from the realtime feed, and where from
+    pub raw_delay: bool,
+    /// Follow each departure with the service day it was expanded from
+    pub service_dates: bool,
+    /// Rate each realtime prediction's confidence
+    pub confidence: bool,
+    /// Follow the departures with a line counting them
+    pub summary: bool,
+    /// Replace each run of consecutive cancelled departures with a line counting them
+    pub compact_cancelled: bool,
+    /// Name trains by their number, like `Train 28 to Gare Centrale`, when they have one
+    pub train_numbers: bool,
+    /// Name of the station to note the trains stopping at later, from `--highlight`
+    pub highlight: Option<String>,
+}
+
+impl Human {
+    /// The departure time, marked and colored as configured.
+    fn time_label(&self, departure: &Departure, ctx: &RenderContext) -> String {
+        let mut label = String::new();
+        if self.mark_approximate && departure.approximate {
+            label.push('~');
+        }
+        let time = departure.time.format(ctx.time_format);
+        if ctx.color {
+            let class = DelayClass::of(departure, self.minor_delay, self.major_delay);
+            write!(label, "\x1b[{}m{time}\x1b[0m", class.ansi_style()).unwrap();
+        } else {
+            write!(label, "{time}").unwrap();
+        }
+        label
+    }
+
+    /// One line per headsign, like `to Gare Centrale: 17:42, 17:55, 18:10`, in order of
+    /// each headsign's first departure.
+    fn render_compact(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let mut groups: Vec<(&str, Vec<String>)> = Vec::new();
+        for departure in departures {
+            let mut label = self.time_label(departure, ctx);
+            if departure.cancelled {
+                write!(label, " ({})", ctx.lang.tr("cancelled")).unwrap();
+            }
+            match groups
+                .iter_mut()
+                .find(|(headsign, _)| *headsign == departure.headsign)
+            {
+                Some((_, labels)) => labels.push(label),
+                None => groups.push((&departure.headsign, vec![label])),
+            }
+        }
+        let mut out = String::new();
+        for (headsign, labels) in groups {
+            let labels = labels.join(", ");
+            writeln!(out, "{}", ctx.lang.fill("to {}: {}", &[&headsign, &labels])).unwrap();
+        }
+        out
+    }
+
+    /// A line like `12 departures, 07:00 to 09:10, 3 delayed by 4 min on average, 1 cancelled`.
+    fn render_summary(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let lang = ctx.lang;
+        let mut line = match departures.len() {
+            1 => lang.tr("1 departure").to_owned(),
+            count => lang.fill("{} departures", &[&count]),
+        };
+        let first = departures.iter().map(|departure| departure.time).min();
+        let last = departures.iter().map(|departure| departure.time).max();
+        if let (Some(first), Some(last)) = (first, last) {
+            line.push_str(&lang.fill(
+                ", {} to {}",
+                &[
+                    &first.format(ctx.time_format),
+                    &last.format(ctx.time_format),
+                ],
+            ));
+        }
+        let delays = departures
+            .iter()
+            .filter(|departure| {
+                matches!(
+                    DelayClass::of(departure, self.minor_delay, self.major_delay),
+                    DelayClass::Minor | DelayClass::Major
+                )
+            })
+            .filter_map(|departure| departure.delay)
+            .collect::<Vec<_>>();
+        if !delays.is_empty() {
+            let average = delays.iter().map(|&delay| i64::from(delay)).sum::<i64>()
+                / delays.len() as i64
+                / 60;
+            line.push_str(&lang.fill(
+                ", {} delayed by {} min on average",
+                &[&delays.len(), &average],
+            ));
+        }
+        let cancelled = departures
+            .iter()
+            .filter(|departure| departure.cancelled)
+            .count();
+        if cancelled > 0 {
+            line.push_str(&lang.fill(", {} cancelled", &[&cancelled]));
+        }
+        line.push('\n');
+        line
+    }
+
+    /// The departures themselves, one line each or grouped by headsign.
+    fn render_departures(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        if self.compact_headsigns {
+            return self.render_compact(departures, ctx);
+        }
+        let last_imminent = self.separate_imminent.and_then(|minutes| {
+            departures
+                .iter()
+                .rposition(|departure| (departure.time - ctx.now).num_minutes() < minutes)
+        });
+        let mut out = String::new();
+        let mut collapsed_until = 0;
+        for (index, departure) in departures.iter().enumerate() {
+            if index < collapsed_until {
+                continue;
+            }
+            if self.compact_cancelled && departure.cancelled {
+                let run = departures[index..]
+                    .iter()
+                    .take_while(|departure| departure.cancelled)
+                    .count();
+                if run > 1 {
+                    collapsed_until = index + run;
+                    let last = &departures[collapsed_until - 1];
+                    let line = ctx.lang.fill(
+                        "{} trains cancelled ({}–{})",
+                        &[
+                            &run,
+                            &departure.time.format(ctx.time_format),
+                            &last.time.format(ctx.time_format),
+                        ],
+                    );
+                    writeln!(out, "{line}").unwrap();
+                    if last_imminent
+                        .is_some_and(|imminent| (index..collapsed_until).contains(&imminent))
+                        && collapsed_until < departures.len()
+                    {
+                        writeln!(out, "{}", "-".repeat(20)).unwrap();
+                    }
+                    continue;
+                }
+            }
+            let time = self.time_label(departure, ctx);
+            if !self.columns.is_empty() {
+                let line = self
+                    .columns
+                    .iter()
+                    .map(|column| column_value(*column, departure, ctx, &time))
+                    .filter(|value| !value.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                out.push_str(&line);
+            } else {
+                let lang = ctx.lang;
+                write!(out, "{time}{}  ", trend_arrow(departure)).unwrap();
+                match &departure.train_number {
+                    Some(number) if self.train_numbers => {
+                        out.push_str(&lang.fill("Train {} to {}", &[number, &departure.headsign]));
+                    }
+                    _ => out.push_str(&departure.headsign),
+                }
+                if departure.cancelled {
+                    write!(out, " ({})", lang.tr("cancelled")).unwrap();
+                } else if departure.time < ctx.now {
+                    write!(out, " ({})", lang.tr("departed")).unwrap();
+                }
+                if departure.extra {
+                    write!(out, " ({})", lang.tr("extra train")).unwrap();
+                }
+                if departure.first_reachable {
+                    write!(out, " ({})", lang.tr("first you can catch")).unwrap();
+                }
+                if let Some(restriction) = departure.restriction {
+                    write!(out, " ({})", lang.tr(restriction)).unwrap();
+                }
+                if let Some(new) = &departure.platform_change {
+                    let mut note = lang.fill("now platform {}", &[new]);
+                    if let Some(scheduled) = &departure.platform {
+                        note.push_str(&lang.fill(", not {}", &[scheduled]));
+                    }
+                    if ctx.color {
+                        // Bold, as boards announce platform changes prominently.
+                        write!(out, " (\x1b[1m{note}\x1b[0m)").unwrap();
+                    } else {
+                        write!(out, " ({note})").unwrap();
+                    }
+                }
+                if let Some(station) = &self.highlight
+                    && departure.highlighted
+                {
+                    let note = lang.fill("stops at {}", &[station]);
+                    if ctx.color {
+                        write!(out, " (\x1b[1m{note}\x1b[0m)").unwrap();
+                    } else {
+                        write!(out, " ({note})").unwrap();
+                    }
+                }
+                if let Some(arrival) = departure.arrival {
+                    let arrives = lang.fill("arrives {}", &[&arrival.format(ctx.time_format)]);
+                    write!(out, " ({arrives}").unwrap();
+                    if self.stop_count
+                        && let Some(stops) = departure.intermediate_stops
+                    {
+                        write!(out, ", {}", stops_label(stops, lang)).unwrap();
+                    }
+                    out.push(')');
+                }
+                if let Some(vehicle) = &departure.vehicle {
+                    write!(out, " ({})", lang.fill("vehicle {}", &[vehicle])).unwrap();
+                }
+                if self.confidence
+                    && let Some(confidence) = Confidence::of_departure(departure, ctx)
+                {
+                    let level = lang.tr(confidence.label());
+                    write!(out, " ({})", lang.fill("{} confidence", &[&level])).unwrap();
+                }
+                if self.through_running {
+                    if let Some(next) = &departure.continues_as {
+                        write!(out, " ({})", lang.fill("continues to {}", &[next])).unwrap();
+                    }
+                    if let Some(block_id) = &departure.block_id {
+                        write!(out, " [{}]", lang.fill("block {}", &[block_id])).unwrap();
+                    }
+                }
+            }
+            if self.raw_delay
+                && let Some(delay) = departure.delay
+            {
+                write!(out, "  [{}", ctx.lang.fill("raw delay {} s", &[&delay])).unwrap();
+                if let Some(source) = &departure.delay_source {
+                    out.push_str(&ctx.lang.fill(" from {}", &[source]));
+                }
+                out.push(']');
+            }
+            if self.service_dates
+                && let Some(date) = departure.service_date
+            {
+                let today = ctx.now.date_naive();
+                let day = match (date - today).num_days() {
+                    -1 => ctx.lang.tr("yesterday"),
+                    0 => ctx.lang.tr("today"),
+                    1 => ctx.lang.tr("tomorrow"),
+                    _ => "",
+                };
+                write!(out, "  [{day} {date}]").unwrap();
+            }
+            out.push('\n');
+            if last_imminent == Some(index) && index + 1 < departures.len() {
+                writeln!(out, "{}", "-".repeat(20)).unwrap();
+            }
+        }
+        out
+    }
+}
+
+impl OutputFormat for Human {
+    fn render(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let mut out = String::new();
+        for note in ctx.notes {
+            writeln!(out, "{}", note.text(ctx.lang)).unwrap();
+        }
+        out.push_str(&self.render_departures(departures, ctx));
+        if self.summary {
+            out.push_str(&self.render_summary(departures, ctx));
+        }
+        out
+    }
+}
+
+/// What `column` shows for `departure`, given its already formatted `time`; empty when the
+/// departure has nothing to show there.
+fn column_value(column: Column, departure: &Departure, ctx: &RenderContext, time: &str) -> String {
+    let now = ctx.now;
+    match column {
+        Column::Time => time.to_owned(),
+        Column::Countdown if departure.time < now => String::new(),
+        Column::Countdown => ctx
+            .lang
+            .fill("in {} min", &[&(departure.time - now).num_minutes()]),
+        Column::Route => departure
+            .route_name
+            .clone()
+            .unwrap_or_else(|| departure.route_id.clone()),
+        Column::Headsign => departure.headsign.clone(),
+        Column::Train => departure.train_number.clone().unwrap_or_default(),
+        Column::Platform => match (&departure.platform, &departure.platform_change) {
+            (Some(scheduled), Some(new)) => {
+                ctx.lang.fill("{} (changed from {})", &[new, scheduled])
+            }
+            (None, Some(new)) => ctx.lang.fill("{} (changed)", &[new]),
+            (platform, None) => platform.clone().unwrap_or_default(),
+        },
+        Column::Delay => match departure.delay.map(|delay| delay / 60) {
+            Some(minutes) if minutes != 0 => {
+                format!("{minutes:+} min{}", trend_arrow(departure))
+            }
+            _ => trend_arrow(departure).trim_start().to_owned(),
+        },
+        Column::Arrival => departure
+            .arrival
+            .map(|arrival| arrival.format(ctx.time_format).to_string())
+            .unwrap_or_default(),
+        Column::Stops => departure
+            .intermediate_stops
+            .map(|stops| stops_label(stops, ctx.lang))
+            .unwrap_or_default(),
+        Column::Confidence => Confidence::of_departure(departure, ctx)
+            .map(|confidence| ctx.lang.tr(confidence.label()).to_owned())
+            .unwrap_or_default(),
+        Column::ServiceDay => departure
+            .service_date
+            .map(|date| date.to_string())
+            .unwrap_or_default(),
+        Column::Status if departure.cancelled => ctx.lang.tr("cancelled").to_owned(),
+        Column::Status if departure.time < now => ctx.lang.tr("departed").to_owned(),
+        Column::Status if departure.extra => ctx.lang.tr("extra train").to_owned(),
+        Column::Status => departure
+            .restriction
+            .map(|restriction| ctx.lang.tr(restriction))
+            .unwrap_or_default()
+            .to_owned(),
+    }
+}
+
+/// ` ↑` when the delay of `departure` grew since the previous refresh, ` ↓` when it shrank.
+fn trend_arrow(departure: &Departure) -> &'static str {
+    match departure.trend {
+        Some(Ordering::Greater) => " ↑",
+        Some(Ordering::Less) => " ↓",
+        _ => "",
+    }
+}
+
+/// Like `non-stop`, `1 stop` or `4 stops`, in `lang`.
+fn stops_label(stops: usize, lang: Lang) -> String {
+    match stops {
+        0 => lang.tr("non-stop").to_owned(),
+        1 => lang.tr("1 stop").to_owned(),
+        stops => lang.fill("{} stops", &[&stops]),
+    }
+}
+
+/// Version of the JSON documents printed by [`Json`] and, line by line, [`Ndjson`].
+///
+/// Consumers may rely on the keys of each departure object keeping their meaning: new keys
+/// can be added at any time, but removing or renaming one, or changing the type or meaning of
+/// its value, requires bumping this version.
+pub const SCHEMA_VERSION: i64 = 1;
+
+/// One departure as a JSON object, with times in RFC 3339 and how confident its prediction is.
+fn departure_json(departure: &Departure, ctx: &RenderContext) -> json::Value {
+    let mut value = departure.to_json_value();
+    if let json::Value::Object(members) = &mut value {
+        members.push((
+            "confidence".into(),
+            Confidence::of_departure(departure, ctx)
+                .map(Confidence::label)
+                .into(),
+        ));
+    }
+    value
+}
+
+/// One JSON object per departure and line, for log ingestion pipelines, after a
+/// `{"note": ...}` object per note.
+pub struct Ndjson;
+
+impl OutputFormat for Ndjson {
+    fn render(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let mut out = String::new();
+        for note in ctx.notes {
+            let value = json::Value::Object(vec![("note".into(), note.text(Lang::En).into())]);
+            writeln!(out, "{value}").unwrap();
+        }
+        for departure in departures {
+            writeln!(out, "{}", departure_json(departure, ctx)).unwrap();
+        }
+        out
+    }
+}
+
+/// A single JSON document: `{"schema_version", "generated_at", "notes": [...],
+/// "departures": [...]}`.
+pub struct Json;
+
+impl OutputFormat for Json {
+    fn render(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let document = json::Value::Object(vec![
+            ("schema_version".into(), SCHEMA_VERSION.into()),
+            ("generated_at".into(), ctx.now.to_rfc3339().into()),
+            (
+                "notes".into(),
+                json::Value::Array(
+                    ctx.notes
+                        .iter()
+                        .map(|note| note.text(Lang::En).into())
+                        .collect(),
+                ),
+            ),
+            (
+                "departures".into(),
+                json::Value::Array(
+                    departures
+                        .iter()
+                        .map(|departure| departure_json(departure, ctx))
+                        .collect(),
+                ),
+            ),
+        ]);
+        format!("{document}\n")
+    }
+}
+
+/// The next departures on one line, like `GC: 17:42 +2 → 18:05`, for status bars.
+pub struct Terse {
+    /// Number of departures on the line
+    pub count: usize,
+    /// Text between departures
+    pub separator: String,
+    /// Follow each time with how many minutes from now it is
+    pub countdown: bool,
+}
+
+impl OutputFormat for Terse {
+    fn render(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let entries = upcoming(departures, ctx.now)
+            .take(self.count)
+            .map(|departure| {
+                let mut entry = departure.time.format(ctx.time_format).to_string();
+                if let Some(minutes) = departure.delay.map(|delay| delay / 60)
+                    && minutes != 0
+                {
+                    write!(entry, " {minutes:+}").unwrap();
+                }
+                entry.push_str(trend_arrow(departure));
+                if self.countdown {
+                    let minutes = (departure.time - ctx.now).num_minutes();
+                    write!(entry, " ({})", ctx.lang.fill("in {} min", &[&minutes])).unwrap();
+                }
+                entry
+            })
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            format!("{}: -\n", ctx.station)
+        } else {
+            format!("{}: {}\n", ctx.station, entries.join(&self.separator))
+        }
+    }
+}
+
+/// Only the minutes until the next departures, like `4 12 25`, for boards too small for times.
+pub struct Countdowns {
+    /// Number of countdowns, per headsign when `per_headsign` is set
+    pub count: usize,
+    /// Print a line per headsign, like `Saint-Jerome: 4 25`, in order of first departure
+    pub per_headsign: bool,
+}
+
+impl OutputFormat for Countdowns {
+    fn render(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let countdown = |departure: &Departure| (departure.time - ctx.now).num_minutes();
+        if !self.per_headsign {
+            let countdowns = upcoming(departures, ctx.now)
+                .take(self.count)
+                .map(|departure| countdown(departure).to_string())
+                .collect::<Vec<_>>();
+            return if countdowns.is_empty() {
+                "-\n".to_owned()
+            } else {
+                format!("{}\n", countdowns.join(" "))
+            };
+        }
+        let mut groups: Vec<(&str, Vec<String>)> = Vec::new();
+        for departure in upcoming(departures, ctx.now) {
+            match groups
+                .iter_mut()
+                .find(|(headsign, _)| *headsign == departure.headsign)
+            {
+                Some((_, countdowns)) if countdowns.len() >= self.count => {}
+                Some((_, countdowns)) => countdowns.push(countdown(departure).to_string()),
+                None if self.count == 0 => {}
+                None => groups.push((&departure.headsign, vec![countdown(departure).to_string()])),
+            }
+        }
+        if groups.is_empty() {
+            return "-\n".to_owned();
+        }
+        let mut out = String::new();
+        for (headsign, countdowns) in groups {
+            writeln!(out, "{headsign}: {}", countdowns.join(" ")).unwrap();
+        }
+        out
+    }
+}
+
+/// A departure board as a self-contained SVG image: the station as a header, then a row per
+/// upcoming departure with its time, route, headsign and delay, as many as fit.
+pub struct Svg {
+    /// Image width in pixels
+    pub width: u32,
+    /// Image height in pixels
+    pub height: u32,
+    /// Delay, in minutes, from which a delay is drawn as slight
+    pub minor_delay: i64,
+    /// Delay, in minutes, from which a delay is drawn as serious
+    pub major_delay: i64,
+}
+
+/// Height of the header and of each row of the SVG board, in pixels.
+const SVG_ROW_HEIGHT: u32 = 32;
+
+impl OutputFormat for Svg {
+    fn render(&self, departures: &[Departure], ctx: &RenderContext) -> String {
+        let (width, height) = (self.width, self.height);
+        let mut out = String::new();
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" font-size="18">"#
+        )
+        .unwrap();
+        writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+        writeln!(
+            out,
+            r#"<rect width="100%" height="{SVG_ROW_HEIGHT}" fill="black"/>"#
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r#"<text x="8" y="23" fill="white" font-weight="bold">{}</text>"#,
+            xml_escape(ctx.station)
+        )
+        .unwrap();
+        let rows = (height / SVG_ROW_HEIGHT).saturating_sub(1) as usize;
+        let upcoming = upcoming(departures, ctx.now).take(rows).collect::<Vec<_>>();
+        if upcoming.is_empty() {
+            writeln!(
+                out,
+                r#"<text x="8" y="{}">{}</text>"#,
+                SVG_ROW_HEIGHT + 23,
+                xml_escape(ctx.lang.tr("No upcoming departures"))
+            )
+            .unwrap();
+        }
+        for (row, departure) in (1..).zip(upcoming) {
+            let y = row * SVG_ROW_HEIGHT + 23;
+            writeln!(
+                out,
+                r#"<text x="8" y="{y}" font-weight="bold">{}</text>"#,
+                xml_escape(&departure.time.format(ctx.time_format).to_string())
+            )
+            .unwrap();
+            writeln!(
+                out,
+                r#"<text x="80" y="{y}">{}</text>"#,
+                xml_escape(departure.route_name.as_ref().unwrap_or(&departure.route_id))
+            )
+            .unwrap();
+            writeln!(
+                out,
+                r#"<text x="130" y="{y}">{}</text>"#,
+                xml_escape(&departure.headsign)
+            )
+            .unwrap();
+            if let Some(minutes) = departure.delay.map(|delay| delay / 60)
+                && minutes != 0
+            {
+                let class = DelayClass::of(departure, self.minor_delay, self.major_delay);
+                writeln!(
+                    out,
+                    r#"<text x="{}" y="{y}" text-anchor="end" fill="{}">{minutes:+} min</text>"#,
+                    width.saturating_sub(8),
+                    class.svg_fill()
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+/// `text` with the characters that are special in XML replaced by entities.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Canada::Eastern;
+
+    use super::*;
+
+    /// A departure running `delay` seconds late, or early when negative.
+    fn delayed(delay: Option<i32>) -> Departure {
+        let time = Eastern.with_ymd_and_hms(2026, 6, 1, 8, 0, 0).unwrap();
+        Departure {
+            delay,
+            ..Departure::new(
+                "t1".to_owned(),
+                "R1".to_owned(),
+                time,
+                "Saint-Jérôme".to_owned(),
+            )
+        }
+    }
+
+    #[test]
+    fn delays_are_classified_from_each_threshold_on() {
+        let class = |delay| DelayClass::of(&delayed(delay), 3, 10);
+        assert_eq!(class(None), DelayClass::OnTime);
+        assert_eq!(class(Some(0)), DelayClass::OnTime);
+        assert_eq!(class(Some(3 * 60 - 1)), DelayClass::OnTime);
+        assert_eq!(class(Some(3 * 60)), DelayClass::Minor);
+        assert_eq!(class(Some(10 * 60 - 1)), DelayClass::Minor);
+        assert_eq!(class(Some(10 * 60)), DelayClass::Major);
+        // Early trains are on time, however early.
+        assert_eq!(class(Some(-59)), DelayClass::OnTime);
+        assert_eq!(class(Some(-20 * 60)), DelayClass::OnTime);
+        let cancelled = Departure {
+            cancelled: true,
+            ..delayed(Some(20 * 60))
+        };
+        assert_eq!(DelayClass::of(&cancelled, 3, 10), DelayClass::Cancelled);
+    }
+
+    #[test]
+    fn confidence_weighs_uncertainty_and_feed_age() {
+        let seconds = |seconds| Some(TimeDelta::seconds(seconds));
+        // Both must be known and small for high confidence.
+        assert_eq!(Confidence::of(Some(60), seconds(120)), Confidence::High);
+        assert_eq!(Confidence::of(Some(0), seconds(0)), Confidence::High);
+        assert_eq!(Confidence::of(Some(61), seconds(120)), Confidence::Medium);
+        assert_eq!(Confidence::of(Some(60), seconds(121)), Confidence::Medium);
+        assert_eq!(Confidence::of(Some(300), seconds(600)), Confidence::Medium);
+        // Either being large is enough for low confidence.
+        assert_eq!(Confidence::of(Some(301), seconds(0)), Confidence::Low);
+        assert_eq!(Confidence::of(Some(0), seconds(601)), Confidence::Low);
+        // Unknown values are never rated high, but don't make a prediction low either.
+        assert_eq!(Confidence::of(None, seconds(0)), Confidence::Medium);
+        assert_eq!(Confidence::of(Some(0), None), Confidence::Medium);
+        assert_eq!(Confidence::of(None, None), Confidence::Medium);
+        assert_eq!(Confidence::of(None, seconds(601)), Confidence::Low);
+        assert_eq!(Confidence::of(Some(301), None), Confidence::Low);
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use gtfs_realtime::FeedMessage;
+use reqwest::Client;
+use tokio::sync::Notify;
+
+use crate::feed;
+
+/// Wait before reconnecting to a stream after a failure, doubled for each further one, unless
+/// the stream sets its own with a `retry:` field.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait before reconnecting to a stream, unless its `retry:` field is longer.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a run waits for the first message of a stream before giving up.
+const FIRST_MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A realtime feed pushed as server-sent events, each event's data being a base64-encoded
+/// `FeedMessage`, kept up to date by a background task from the first time it is read.
+///
+/// Messages whose header is DIFFERENTIAL update the entities of the previous ones by id,
+/// others replace the whole feed.
+#[derive(Debug, Default)]
+pub struct Stream {
+    started: OnceLock<()>,
+    latest: Mutex<Option<FeedMessage>>,
+    updated: Notify,
+}
+
+impl Stream {
+    /// The realtime feed as last received from `url`, waiting for the first message if none
+    /// came yet. Messages larger than `max_size` bytes end the connection.
+    pub async fn latest(
+        self: &Arc<Self>,
+        client: &Client,
+        url: &str,
+        max_size: usize,
+    ) -> anyhow::Result<FeedMessage> {
+        self.started.get_or_init(|| {
+            tokio::spawn(Arc::clone(self).listen(client.clone(), url.to_owned(), max_size));
+        });
+        let received = async {
+            loop {
+                let updated = self.updated.notified();
+                if let Some(message) = self.latest.lock().unwrap().clone() {
+                    return message;
+                }
+                updated.await;
+            }
+        };
+        tokio::time::timeout(FIRST_MESSAGE_TIMEOUT, received)
+            .await
+            .context("No message received from the realtime stream yet")
+    }
+
+    /// Waits until the next message is received.
+    pub async fn next_update(&self) {
+        self.updated.notified().await;
+    }
+
+    /// Reads the stream for good, reconnecting with exponential backoff whenever it ends.
+    async fn listen(self: Arc<Self>, client: Client, url: String, max_size: usize) {
+        let mut reconnect = Reconnect::default();
+        loop {
+            let result = self.read(&client, &url, max_size, &mut reconnect).await;
+            let wait = reconnect.failed();
+            match result {
+                Ok(()) => eprintln!(
+                    "WARNING: The realtime stream closed, reconnecting in {} s",
+                    wait.as_secs()
+                ),
+                Err(err) => eprintln!(
+                    "WARNING: The realtime stream failed ({err:#}), reconnecting in {} s",
+                    wait.as_secs()
+                ),
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Applies the messages of one connection to `url` until it ends, or one of them is larger
+    /// than `max_size` bytes, telling `reconnect` about each message and `retry:` field.
+    async fn read(
+        &self,
+        client: &Client,
+        url: &str,
+        max_size: usize,
+        reconnect: &mut Reconnect,
+    ) -> anyhow::Result<()> {
+        let mut response = client
+            .get(url)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?
+            .error_for_status()?;
+        let mut events = Events::default();
+        while let Some(chunk) = response.chunk().await? {
+            // Base64 takes four bytes for every three of the message.
+            if (events.pending() + chunk.len()) / 4 * 3 > max_size {
+                bail!(
+                    "A message of the realtime stream is larger than the limit of {} MB",
+                    max_size / (1024 * 1024)
+                );
+            }
+            let data = events.push(&chunk);
+            if let Some(retry) = events.retry.take() {
+                reconnect.retry(retry);
+            }
+            for data in data {
+                let bytes = decode_base64(&data)?;
+                let message: FeedMessage = prost::Message::decode(bytes.as_ref())
+                    .context("Could not decode a message of the realtime stream")?;
+                self.apply(message);
+                reconnect.received();
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges `message` into the latest feed and wakes up whoever waits for it.
+    fn apply(&self, message: FeedMessage) {
+        let mut latest = self.latest.lock().unwrap();
+        *latest = Some(feed::apply_incremental(latest.take(), message));
+        drop(latest);
+        self.updated.notify_waiters();
+    }
+}
+
+/// The server-sent events of one connection, read as its bytes come in.
+#[derive(Debug, Default)]
+struct Events {
+    /// Bytes of the line being received
+    buffer: Vec<u8>,
+    /// Data of the event being received, its `data:` lines joined
+    data: String,
+    /// Reconnection delay last set with a `retry:` field
+    retry: Option<Duration>,
+}
+
+impl Events {
+    /// Adds `chunk` to the connection, returning the data of each event it completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line = self.buffer.drain(..=end).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if let Some(value) = line.strip_prefix("data:") {
+                // Data is base64, so lines are joined without the spaces around them.
+                self.data.push_str(value.trim());
+            } else if let Some(value) = line.strip_prefix("retry:") {
+                // Malformed delays are ignored, as the spec says.
+                if let Ok(millis) = value.trim().parse() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            } else if line.is_empty() && !self.data.is_empty() {
+                // A blank line ends the event.
+                events.push(std::mem::take(&mut self.data));
+            }
+            // Event names, ids and comments don't matter here.
+        }
+        events
+    }
+
+    /// Number of bytes received for the event being received so far.
+    fn pending(&self) -> usize {
+        self.buffer.len() + self.data.len()
+    }
+}
+
+/// How long to wait before each reconnection to a stream.
+#[derive(Debug)]
+struct Reconnect {
+    /// Wait after the first failure in a row
+    base: Duration,
+    /// Wait after the next failure
+    backoff: Duration,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Reconnect {
+            base: RECONNECT_BACKOFF,
+            backoff: RECONNECT_BACKOFF,
+        }
+    }
+}
+
+impl Reconnect {
+    /// A message was received, so the next failure is the first in a row.
+    fn received(&mut self) {
+        self.backoff = self.base;
+    }
+
+    /// The stream asked to wait `delay` before reconnecting.
+    fn retry(&mut self, delay: Duration) {
+        self.base = delay;
+        self.backoff = delay;
+    }
+
+    /// The wait after a failure, doubling the one after the next failure.
+    fn failed(&mut self) -> Duration {
+        let wait = self.backoff;
+        self.backoff = (wait * 2).min(MAX_RECONNECT_BACKOFF.max(self.base));
+        wait
+    }
+}
+
+/// Decodes base64, in its standard or URL-safe alphabet, ignoring padding and whitespace.
+fn decode_base64(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => continue,
+            c if c.is_ascii_whitespace() => continue,
+            c => bail!("Invalid base64 character `{}`", c.escape_ascii()),
+        };
+        bits = bits << 6 | u32::from(value);
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_decodes_with_or_without_padding() {
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("Zg==").unwrap(), b"f");
+        assert_eq!(decode_base64("Zg").unwrap(), b"f");
+        assert_eq!(decode_base64("Zm8=").unwrap(), b"fo");
+        assert_eq!(decode_base64("Zm9v").unwrap(), b"foo");
+        assert_eq!(decode_base64("Zm9v\nYmFy").unwrap(), b"foobar");
+        // Both alphabets, for the bytes 0xfb 0xff.
+        assert_eq!(decode_base64("+/8=").unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode_base64("-_8").unwrap(), [0xfb, 0xff]);
+        let err = decode_base64("Zm9v!").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid base64 character `!`");
+        assert!(decode_base64("Zm9vé").is_err());
+    }
+
+    #[test]
+    fn events_are_read_across_chunks_and_lines() {
+        let mut events = Events::default();
+        assert!(
+            events
+                .push(b": a comment\nevent: update\nid: 1\ndata: Zm9v")
+                .is_empty()
+        );
+        assert_eq!(events.pending(), 10);
+        assert!(events.push(b"\r\ndata:YmFy\r\n").is_empty());
+        assert_eq!(events.push(b"\r\n"), ["Zm9vYmFy"]);
+        assert_eq!(events.pending(), 0);
+        // Blank lines outside of events, then two events in one chunk.
+        assert_eq!(
+            events.push(b"\n\ndata: Zg==\n\ndata: Zm8=\n\ndata: Zm"),
+            ["Zg==", "Zm8="]
+        );
+        assert_eq!(events.retry, None);
+        events.push(b"9v\nretry: soon\nretry: 5000\n");
+        assert_eq!(events.retry, Some(Duration::from_secs(5)));
+        assert_eq!(events.push(b"\n"), ["Zm9v"]);
+    }
+
+    #[test]
+    fn reconnections_back_off_until_a_message_arrives() {
+        let mut reconnect = Reconnect::default();
+        let waits = |reconnect: &mut Reconnect, count| {
+            (0..count)
+                .map(|_| reconnect.failed().as_secs())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(waits(&mut reconnect, 8), [1, 2, 4, 8, 16, 32, 60, 60]);
+        reconnect.received();
+        assert_eq!(waits(&mut reconnect, 2), [1, 2]);
+        reconnect.retry(Duration::from_secs(5));
+        assert_eq!(waits(&mut reconnect, 5), [5, 10, 20, 40, 60]);
+        reconnect.received();
+        assert_eq!(waits(&mut reconnect, 1), [5]);
+        // A longer delay than the usual longest wait is still honored.
+        reconnect.retry(Duration::from_secs(90));
+        assert_eq!(waits(&mut reconnect, 2), [90, 90]);
+    }
+}